@@ -0,0 +1,177 @@
+// The MIT License
+// Copyright 2020 Peter Mezei <mezeipetister@gmail.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+// Made with (L) from Hungary
+// If you need any help please contact me
+// at <mezeipetister@gmail.com>
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitStr};
+
+/// #[derive(PackRepository)]
+///
+/// Implements packman::PackRepository for a struct
+/// with named fields. Every field marked with
+/// #[pack(path = "..")] is loaded (or initialized)
+/// from that sub path of the repository root, every
+/// other field is created by Default::default().
+///
+/// ```ignore
+/// #[derive(PackRepository)]
+/// struct Repository {
+///     #[pack(path = "config")]
+///     config: Pack<Config>,
+///     #[pack(path = "users")]
+///     users: VecPack<User>,
+/// }
+/// ```
+#[proc_macro_derive(PackRepository, attributes(pack))]
+pub fn derive_pack_repository(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) =
+        input.generics.split_for_impl();
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    name,
+                    "PackRepository requires a struct with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                name,
+                "PackRepository can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut loaders = Vec::new();
+    let mut initializers = Vec::new();
+    let mut savers = Vec::new();
+    let mut flushers = Vec::new();
+
+    for field in fields {
+        let ident = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+        let label = ident.to_string();
+        match pack_path(&field.attrs)? {
+            Some(path) => {
+                loaders.push(quote! {
+                    let #ident = match <#ty as ::packman::RepositoryMember>
+                        ::load_member(&root, #path)
+                    {
+                        Ok(member) => Some(member),
+                        Err(err) => {
+                            errors.push((#label.to_string(), err));
+                            None
+                        }
+                    };
+                });
+                initializers.push(quote! { #ident: #ident.unwrap() });
+                savers.push(quote! {
+                    if let Err(err) =
+                        ::packman::RepositoryMember::save_member(&self.#ident)
+                    {
+                        errors.push((#label.to_string(), err));
+                    }
+                });
+                flushers.push(quote! {
+                    if let Err(err) =
+                        ::packman::RepositoryMember::flush_member(&self.#ident)
+                    {
+                        errors.push((#label.to_string(), err));
+                    }
+                });
+            }
+            None => initializers.push(quote! {
+                #ident: ::std::default::Default::default()
+            }),
+        }
+    }
+
+    Ok(quote! {
+        impl #impl_generics ::packman::PackRepository for #name #ty_generics
+            #where_clause
+        {
+            fn load_or_init(
+                root: ::std::path::PathBuf,
+            ) -> ::packman::PackResult<Self> {
+                let mut errors: Vec<(String, ::packman::PackError)> =
+                    Vec::new();
+                #(#loaders)*
+                if !errors.is_empty() {
+                    return Err(::packman::PackError::RepositoryError(errors));
+                }
+                Ok(Self { #(#initializers),* })
+            }
+            fn save_all(&self) -> ::packman::PackResult<()> {
+                let mut errors: Vec<(String, ::packman::PackError)> =
+                    Vec::new();
+                #(#savers)*
+                if !errors.is_empty() {
+                    return Err(::packman::PackError::RepositoryError(errors));
+                }
+                Ok(())
+            }
+            fn flush(&self) -> ::packman::PackResult<()> {
+                let mut errors: Vec<(String, ::packman::PackError)> =
+                    Vec::new();
+                #(#flushers)*
+                if !errors.is_empty() {
+                    return Err(::packman::PackError::RepositoryError(errors));
+                }
+                Ok(())
+            }
+        }
+    })
+}
+
+// Find #[pack(path = "..")] among the field attributes
+fn pack_path(attrs: &[syn::Attribute]) -> syn::Result<Option<LitStr>> {
+    let mut path = None;
+    for attr in attrs.iter().filter(|a| a.path().is_ident("pack")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("path") {
+                path = Some(meta.value()?.parse::<LitStr>()?);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported pack attribute, expected `path`"))
+            }
+        })?;
+    }
+    Ok(path)
+}
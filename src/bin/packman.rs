@@ -7,6 +7,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut need_json = true;
     let mut need_bytes = false;
     let mut need_inodes = false;
+    let mut need_backup = false;
     let path: String = match args.len() {
         x if x > 1 => match args[1].parse() {
             Ok(p) => p,
@@ -22,15 +23,17 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         "--json" => need_json = true,
         "--bytes" => need_bytes = true,
         "--inodes" => need_inodes = true,
+        "--backup" => need_backup = true,
         _ => (),
     });
-    let mut pack_file: PackFile = match PackFile::open(Path::new(&path)) {
-        Ok(pf) => pf,
-        Err(err) => {
-            println!("{}", err);
-            return Ok(());
-        }
-    };
+    let mut pack_file: PackFile =
+        match PackFile::open_read_only(Path::new(&path)) {
+            Ok(pf) => pf,
+            Err(err) => {
+                println!("{}", err);
+                return Ok(());
+            }
+        };
     let details = pack_file.metadata();
     let json_data_bytes: Vec<u8> =
         pack_file.load_data().expect("Error loading data");
@@ -75,5 +78,23 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     if need_json {
         println!("Pack data json: {}", json_data_string);
     }
+    if need_backup {
+        match pack_file.load_backup() {
+            Ok(backup_bytes) => {
+                println!(
+                    "Backup version: {}",
+                    std::cmp::min(
+                        details.inode_version_a,
+                        details.inode_version_b
+                    )
+                );
+                println!(
+                    "Pack backup json: {}",
+                    String::from_utf8_lossy(&backup_bytes)
+                );
+            }
+            Err(err) => println!("Pack backup: {}", err),
+        }
+    }
     Ok(())
 }
@@ -228,12 +228,18 @@ pub struct PackFile {
   pub inodes: [Inode; 2],
   file_ptr: File,
   path: PathBuf,
+  read_only: bool,
+  file_key: Option<(u64, u64)>, // (device, inode) of the open file
+  stamp: Option<(std::time::SystemTime, u64)>, // (mtime, size) of the inodes
 }
 
 impl PackFile {
   pub fn get_id(&self) -> u64 {
     self.superblock.id
   }
+  pub fn get_workspace_id(&self) -> Option<u64> {
+    self.superblock.get_workspace_id()
+  }
   pub fn metadata(&self) -> Metadata {
     Metadata {
       path: self.path.to_str().unwrap_or("ERROR").to_owned(),
@@ -297,8 +303,65 @@ impl PackFile {
   // Open file if it exists
   // otherwise error
   pub fn open(path: &Path) -> PackResult<PackFile> {
+    PackFile::open_with(path, false)
+  }
+
+  // Open file as read-only if it exists
+  // otherwise error. Every write returns
+  // PackError::ReadOnly
+  pub fn open_read_only(path: &Path) -> PackResult<PackFile> {
+    PackFile::open_with(path, true)
+  }
+
+  pub fn is_read_only(&self) -> bool {
+    self.read_only
+  }
+
+  // Version of the latest inode
+  // Same as metadata().file_version, without
+  // reading the file size
+  pub fn get_version(&self) -> u64 {
+    self.get_latest_inode().get_version()
+  }
+
+  // True if path still refers to the open file
+  // False if the packfile has been removed or replaced since
+  // it has been opened. If the file has been modified since
+  // its inodes were read or written, then they are read again.
+  pub fn check_current(&mut self) -> PackResult<bool> {
+    let meta = match std::fs::metadata(&self.path) {
+      Ok(meta) if meta.is_file() && util::file_key(&meta) == self.file_key => {
+        meta
+      }
+      _ => return Ok(false),
+    };
+    if util::stamp(&meta) != self.stamp {
+      self.refresh()?;
+    }
+    Ok(true)
+  }
+
+  // Read both inodes again
+  // Use it when the packfile could be written
+  // by someone else since it has been opened
+  pub fn refresh(&mut self) -> PackResult<()> {
+    // Taken first, so a write while reading
+    // is found by the next check_current()
+    self.stamp = util::stamp(&self.file_ptr.metadata()?);
+    let mut reader = BufReader::new(&self.file_ptr);
+    reader.seek(SeekFrom::Start(util::inode_offset_first()))?;
+    let inode_a = Inode::deserialize_from(&mut reader)?;
+    reader.seek(SeekFrom::Start(util::inode_offset_second()))?;
+    let inode_b = Inode::deserialize_from(&mut reader)?;
+    self.inodes = [inode_a, inode_b];
+    Ok(())
+  }
+
+  fn open_with(path: &Path, read_only: bool) -> PackResult<PackFile> {
     // Try open or error
-    let file = OpenOptions::new().read(true).write(true).open(path)?;
+    let file = OpenOptions::new().read(true).write(!read_only).open(path)?;
+
+    let meta = file.metadata()?;
 
     // Create a bufreader to read bytes
     let mut reader = BufReader::new(&file);
@@ -343,6 +406,9 @@ impl PackFile {
       inodes: [inode_a, inode_b],
       file_ptr: file,
       path: PathBuf::from(path),
+      read_only,
+      file_key: util::file_key(&meta),
+      stamp: util::stamp(&meta),
     };
 
     Ok(pfile)
@@ -359,16 +425,27 @@ impl PackFile {
         PackError::PckflDataError => {
           // TODO: here we should trace or log?
           // TODO!: also we must set the version to 0 for the current latest inode TRUNCATE
+          // (never in read-only mode)
           self.get_backup_inode().load_data(&mut reader)
         }
         _ => Err(err),
       },
     }
   }
+  // Load data of the backup inode
+  // Error if there is no previous version
   pub fn load_backup(&mut self) -> PackResult<Vec<u8>> {
+    if !self.has_backup() {
+      return Err(PackError::NoBackup);
+    }
     let mut reader = BufReader::new(&self.file_ptr);
     self.get_backup_inode().load_data(&mut reader)
   }
+  // True if the backup inode
+  // holds a previous version
+  pub fn has_backup(&self) -> bool {
+    self.get_backup_inode().get_version() > 0
+  }
   fn save_data(&mut self, data: &[u8]) -> PackResult<()> {
     todo!()
   }
@@ -473,6 +550,9 @@ impl PackFile {
     Ok(())
   }
   pub fn write_data(&mut self, data: &[u8]) -> PackResult<()> {
+    if self.read_only {
+      return Err(PackError::ReadOnly);
+    }
     let current_file_len = match self.file_ptr.metadata() {
       Ok(file_meta) => file_meta.len(),
       Err(_) => 0,
@@ -533,10 +613,71 @@ impl PackFile {
     cursor.write_all(data)?;
     // bincode::serialize_into(&mut cursor, &data)?;
     cursor.flush()?;
+    drop(cursor);
+
+    // Keep the in-memory inodes in sync,
+    // so the open PackFile can be written again
+    match latest_position {
+      InodePosition::First => self.inodes[1] = new_inode,
+      InodePosition::Second => self.inodes[0] = new_inode,
+    }
+    self.stamp = util::stamp(&self.file_ptr.metadata()?);
     Ok(())
   }
 }
 
+// Remove packfile
+pub fn remove_file(path: &Path) -> PackResult<()> {
+  std::fs::remove_file(path)?;
+  Ok(())
+}
+
+// Move packfile to a new path
+// If target already exists, returns PackError::IDTaken. Within
+// one filesystem the packfile is renamed, so it is never at both
+// paths; the check and the rename are not one step, so the caller
+// must own the target directory (e.g. by &mut VecPack<T>).
+// A target which is a hard link of the source (left by a move
+// interrupted between link and remove) is kept, and the source
+// is removed. Between filesystems it falls back to copy and
+// remove; on error the target is removed, and the source is kept.
+pub fn move_file(from: &Path, to: &Path) -> PackResult<()> {
+  if let Some(parent) = to.parent() {
+    std::fs::create_dir_all(parent)?;
+  }
+  if let Ok(target) = std::fs::symlink_metadata(to) {
+    let source = std::fs::metadata(from)?;
+    return match util::file_key(&target) {
+      Some(key) if Some(key) == util::file_key(&source) => remove_file(from),
+      _ => Err(PackError::IDTaken),
+    };
+  }
+  match std::fs::rename(from, to) {
+    Err(err) if err.kind() == std::io::ErrorKind::CrossesDevices => (),
+    res => return Ok(res?),
+  }
+  let mut source = File::open(from)?;
+  let mut target =
+    match OpenOptions::new().write(true).create_new(true).open(to) {
+      Ok(target) => target,
+      Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+        return Err(PackError::IDTaken);
+      }
+      Err(err) => return Err(err.into()),
+    };
+  // A partial copy would take the ID of the next try
+  let copied =
+    std::io::copy(&mut source, &mut target).and_then(|_| target.sync_all());
+  if let Err(err) = copied {
+    let _ = std::fs::remove_file(to);
+    return Err(err.into());
+  }
+  // Both files left would take the ID twice
+  remove_file(from).inspect_err(|_| {
+    let _ = std::fs::remove_file(to);
+  })
+}
+
 enum InodePosition {
   First,
   Second,
@@ -576,6 +717,27 @@ mod util {
       .as_secs()
   }
 
+  // (device, inode) of a file
+  // It identifies the file, even after a rename
+  #[cfg(unix)]
+  pub(crate) fn file_key(meta: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+  }
+
+  #[cfg(not(unix))]
+  pub(crate) fn file_key(_meta: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+  }
+
+  // (mtime, size) of a file
+  // It changes when the file is written
+  pub(crate) fn stamp(
+    meta: &std::fs::Metadata,
+  ) -> Option<(time::SystemTime, u64)> {
+    meta.modified().ok().map(|modified| (modified, meta.len()))
+  }
+
   #[inline]
   pub(crate) fn superblock_offset() -> u64 {
     0
@@ -0,0 +1,199 @@
+//! IDs of VecPack members
+//!
+//! VecPack::insert_new() generates the ID of a new member by the
+//! IdStrategy of its VecPack<T>, which is recorded in the manifest of
+//! the VecPack directory. The counter strategy persists its last value
+//! in a dotfile inside the VecPack directory, which is not loaded as a
+//! member.
+//!
+//! Member files are named by their encoded ID. Characters that are
+//! not safe in a filename on every platform are percent-encoded, so
+//! any ID stays inside the VecPack directory, and the filename can
+//! be decoded back to the ID.
+
+use crate::*;
+use std::io::Write;
+
+/// Name of the counter file in the VecPack directory
+pub const COUNTER_FILE: &str = ".id_counter";
+
+// Lock file of the counter, held while it is incremented
+const COUNTER_LOCK: &str = ".id_counter.lock";
+
+type Generator = Arc<dyn Fn() -> String + Send + Sync>;
+
+/// ID generation strategy of a VecPack<T>
+#[derive(Clone, Default)]
+pub enum IdStrategy {
+    /// Random nanoid, e.g. "V1StGXR8_Z5jdHi6B-myT"
+    #[default]
+    NanoId,
+    /// Random UUID v4
+    Uuid,
+    /// 1, 2, 3.. persisted in the VecPack directory,
+    /// so IDs are not reused after a reload
+    Counter,
+    /// User-defined generator
+    Custom(Generator),
+}
+
+impl IdStrategy {
+    /// Custom strategy from a generator closure
+    pub fn custom<F>(f: F) -> Self
+    where
+        F: Fn() -> String + Send + Sync + 'static,
+    {
+        IdStrategy::Custom(Arc::new(f))
+    }
+    // Next ID candidate
+    // dir is the VecPack directory
+    pub(crate) fn generate(&self, dir: &Path) -> PackResult<String> {
+        match self {
+            IdStrategy::NanoId => Ok(nanoid::nanoid!()),
+            IdStrategy::Uuid => Ok(uuid::Uuid::new_v4().to_string()),
+            IdStrategy::Counter => next_counter(dir).map(|c| c.to_string()),
+            IdStrategy::Custom(f) => Ok(f()),
+        }
+    }
+    // Name recorded in the manifest
+    // Custom strategies can not be recorded
+    pub(crate) fn name(&self) -> Option<&'static str> {
+        match self {
+            IdStrategy::NanoId => Some("nanoid"),
+            IdStrategy::Uuid => Some("uuid"),
+            IdStrategy::Counter => Some("counter"),
+            IdStrategy::Custom(_) => None,
+        }
+    }
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "nanoid" => Some(IdStrategy::NanoId),
+            "uuid" => Some(IdStrategy::Uuid),
+            "counter" => Some(IdStrategy::Counter),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for IdStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IdStrategy::NanoId => write!(f, "NanoId"),
+            IdStrategy::Uuid => write!(f, "Uuid"),
+            IdStrategy::Counter => write!(f, "Counter"),
+            IdStrategy::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+// Increment the counter of the VecPack directory
+// The new value is written to a temp file first, then
+// renamed, so the counter is never half written. Other
+// handles and processes wait for the lock file, so no
+// two of them get the same value.
+fn next_counter(dir: &Path) -> PackResult<u64> {
+    let lock = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(COUNTER_LOCK))?;
+    // Released when lock is dropped, or the process exits
+    lock.lock()?;
+    let path = dir.join(COUNTER_FILE);
+    let last = match std::fs::read_to_string(&path) {
+        Ok(content) => content
+            .trim()
+            .parse::<u64>()
+            .map_err(|err| PackError::DeserializeError(err.to_string()))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+        Err(e) => return Err(e.into()),
+    };
+    let next = last + 1;
+    let tmp = dir.join(format!("{}.tmp", COUNTER_FILE));
+    let mut file = std::fs::File::create(&tmp)?;
+    file.write_all(next.to_string().as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, &path)?;
+    Ok(next)
+}
+
+// Names reserved by Windows, with or without extension
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6",
+    "COM7", "COM8", "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6",
+    "LPT7", "LPT8", "LPT9",
+];
+
+/// Filename of a VecPack member by its ID
+/// Percent-encodes '%', path separators, control and
+/// reserved characters, a leading '.', a trailing '.' or
+/// space, and the first character of reserved names.
+/// An empty ID is encoded as "%".
+///
+/// ```rust
+/// use packman::id::*;
+/// assert_eq!(encode_filename("a/../b"), "a%2F..%2Fb");
+/// assert_eq!(decode_filename("a%2F..%2Fb").unwrap(), "a/../b");
+/// ```
+pub fn encode_filename(id: &str) -> String {
+    if id.is_empty() {
+        return "%".to_string();
+    }
+    let stem = id.split('.').next().unwrap_or_default();
+    let reserved = RESERVED_NAMES
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case(stem));
+    let last = id.chars().count() - 1;
+    let mut result = String::with_capacity(id.len());
+    for (i, c) in id.chars().enumerate() {
+        let escape = match c {
+            '%' | '/' | '\\' | '<' | '>' | ':' | '"' | '|' | '?' | '*' => true,
+            c if c.is_control() => true,
+            '.' => i == 0 || i == last,
+            ' ' => i == last,
+            _ => i == 0 && reserved,
+        };
+        if escape {
+            let mut bytes = [0; 4];
+            for b in c.encode_utf8(&mut bytes).bytes() {
+                result.push_str(&format!("%{:02X}", b));
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// ID of a VecPack member by its filename
+/// Returns None if the name is not the
+/// encode_filename() result of any ID.
+pub fn decode_filename(name: &str) -> Option<String> {
+    if name == "%" {
+        return Some(String::new());
+    }
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut rest = name.as_bytes();
+    while let Some((b, tail)) = rest.split_first() {
+        if *b == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(*b);
+            rest = tail;
+        }
+    }
+    let id = String::from_utf8(bytes).ok()?;
+    // Only one filename per ID
+    if encode_filename(&id) != name {
+        return None;
+    }
+    Some(id)
+}
+
+// Filename key of an ID, the same for IDs whose
+// filenames collide on case-insensitive filesystems
+pub(crate) fn fold(id: &str) -> String {
+    encode_filename(id).to_lowercase()
+}
@@ -0,0 +1,411 @@
+//! Member indexes
+//!
+//! VecPack<T> keeps a hash index from the member IDs to their
+//! positions, so finding a member by its ID does not scan every
+//! member. User-defined secondary indexes map a key extracted from T
+//! to the positions of the members with that key. Members share the
+//! indexes of their VecPack<T>, and update them on every save.
+
+use crate::*;
+use std::collections::HashMap;
+use std::sync::MutexGuard;
+
+/// Kind of a secondary index
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IndexKind {
+    /// At most one member per key, inserts and saves
+    /// breaking it return PackError::UniqueIndexViolation
+    Unique,
+    /// Any number of members per key
+    Multi,
+}
+
+type KeyFn<T> = Arc<dyn Fn(&T) -> String + Send + Sync>;
+
+// User-defined index
+struct Secondary<T> {
+    name: String,
+    kind: IndexKind,
+    key: KeyFn<T>,
+    // Key -> positions
+    entries: HashMap<String, Vec<usize>>,
+}
+
+impl<T> Secondary<T> {
+    fn add(&mut self, key: String, position: usize) {
+        self.entries.entry(key).or_default().push(position);
+    }
+    fn remove(&mut self, key: &str, position: usize) {
+        if let Some(positions) = self.entries.get_mut(key) {
+            positions.retain(|p| *p != position);
+            if positions.is_empty() {
+                self.entries.remove(key);
+            }
+        }
+    }
+    // Error if an other member has the key of new
+    fn check(&self, old: Option<&T>, new: &T) -> PackResult<()> {
+        if self.kind != IndexKind::Unique {
+            return Ok(());
+        }
+        let key = (self.key)(new);
+        if old.is_some_and(|old| (self.key)(old) == key) {
+            return Ok(());
+        }
+        if self.entries.contains_key(&key) {
+            return Err(PackError::UniqueIndexViolation(
+                self.name.clone(),
+                key,
+            ));
+        }
+        Ok(())
+    }
+}
+
+struct IndexInner<T> {
+    id_key: fn(&T) -> String,
+    // ID (formatted by Display) -> position
+    positions: HashMap<String, usize>,
+    // Case folded filename -> ID
+    folded: HashMap<String, String>,
+    secondary: Vec<Secondary<T>>,
+    // Members could be changed without the indexes,
+    // they must be built again before use
+    stale: bool,
+}
+
+impl<T> IndexInner<T> {
+    // Error if an other member has the same
+    // filename on case-insensitive filesystems
+    fn check_collision(&self, key: &str) -> PackResult<()> {
+        match self.folded.get(&id::fold(key)) {
+            Some(taken) if taken != key => {
+                Err(PackError::IDCollision(key.to_string(), taken.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+    fn check_insert(&self, item: &T) -> PackResult<()> {
+        self.check_collision(&(self.id_key)(item))?;
+        self.secondary.iter().try_for_each(|s| s.check(None, item))
+    }
+    fn forget(&mut self, item: &T, position: usize) {
+        let key = (self.id_key)(item);
+        self.folded.remove(&id::fold(&key));
+        self.positions.remove(&key);
+        for secondary in self.secondary.iter_mut() {
+            secondary.remove(&(secondary.key)(item), position);
+        }
+    }
+    fn insert(&mut self, item: &T, position: usize) {
+        let key = (self.id_key)(item);
+        self.folded.insert(id::fold(&key), key.clone());
+        self.positions.insert(key, position);
+        for secondary in self.secondary.iter_mut() {
+            secondary.add((secondary.key)(item), position);
+        }
+    }
+    fn check(&self, old: &T, new: &T) -> PackResult<()> {
+        let (old_id, new_id) = ((self.id_key)(old), (self.id_key)(new));
+        if old_id != new_id {
+            if self.positions.contains_key(&new_id) {
+                return Err(PackError::IDTaken);
+            }
+            if id::fold(&old_id) != id::fold(&new_id) {
+                self.check_collision(&new_id)?;
+            }
+        }
+        self.secondary
+            .iter()
+            .try_for_each(|s| s.check(Some(old), new))
+    }
+    fn changed(&mut self, old: &T, new: &T) {
+        let (old_id, new_id) = ((self.id_key)(old), (self.id_key)(new));
+        let position = match self.positions.remove(&old_id) {
+            Some(position) => position,
+            None => {
+                self.stale = true;
+                return;
+            }
+        };
+        self.folded.remove(&id::fold(&old_id));
+        self.folded.insert(id::fold(&new_id), new_id.clone());
+        self.positions.insert(new_id, position);
+        for secondary in self.secondary.iter_mut() {
+            let (old_key, new_key) =
+                ((secondary.key)(old), (secondary.key)(new));
+            if old_key != new_key {
+                secondary.remove(&old_key, position);
+                secondary.add(new_key, position);
+            }
+        }
+    }
+    // True if new has an other ID or secondary key than old
+    fn keys_changed(&self, old: &T, new: &T) -> bool {
+        (self.id_key)(old) != (self.id_key)(new)
+            || self.secondary.iter().any(|s| (s.key)(old) != (s.key)(new))
+    }
+}
+
+// Indexes of a VecPack<T>
+// The ID key is a fn pointer, as Pack<T>
+// does not know that T is a VecPackMember
+pub(crate) struct IdIndex<T> {
+    inner: Arc<Mutex<IndexInner<T>>>,
+}
+
+impl<T> Clone for IdIndex<T> {
+    fn clone(&self) -> Self {
+        IdIndex {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> IdIndex<T> {
+    pub(crate) fn new(id_key: fn(&T) -> String) -> Self {
+        IdIndex {
+            inner: Arc::new(Mutex::new(IndexInner {
+                id_key,
+                positions: HashMap::new(),
+                folded: HashMap::new(),
+                secondary: Vec::new(),
+                stale: false,
+            })),
+        }
+    }
+    // Position of a member by its ID key
+    pub(crate) fn get(&self, key: &str) -> Option<usize> {
+        self.lock().positions.get(key).copied()
+    }
+    // True if a new member can have the ID key
+    pub(crate) fn is_available(&self, key: &str) -> bool {
+        let inner = self.lock();
+        !inner.positions.contains_key(key)
+            && !inner.folded.contains_key(&id::fold(key))
+    }
+    // Positions of the members by a secondary index key
+    // Returns PackError::ObjectNotFound if there is no
+    // index with the given name
+    pub(crate) fn find_by(
+        &self,
+        index: &str,
+        key: &str,
+    ) -> PackResult<Vec<usize>> {
+        let inner = self.lock();
+        match inner.secondary.iter().find(|s| s.name == index) {
+            Some(secondary) => {
+                Ok(secondary.entries.get(key).cloned().unwrap_or_default())
+            }
+            None => Err(PackError::ObjectNotFound),
+        }
+    }
+    // Error if the filename of a new member would collide
+    // with an other one, or it would break a unique index
+    // The index must be built again first if it is stale
+    pub(crate) fn check_insert(&self, item: &T) -> PackResult<()> {
+        self.lock().check_insert(item)
+    }
+    pub(crate) fn insert(&self, item: &T, position: usize) {
+        self.lock().insert(item, position)
+    }
+    // Check a new member, save it, then add it at position
+    // The indexes stay locked while it is saved, so an
+    // other member cannot take its keys in between.
+    pub(crate) fn insert_with<F>(
+        &self,
+        item: &T,
+        position: usize,
+        save: F,
+    ) -> PackResult<()>
+    where
+        F: FnOnce() -> PackResult<()>,
+    {
+        let mut inner = self.lock();
+        if inner.positions.contains_key(&(inner.id_key)(item)) {
+            return Err(PackError::IDTaken);
+        }
+        inner.check_insert(item)?;
+        save()?;
+        inner.insert(item, position);
+        Ok(())
+    }
+    // Remove the member at position
+    // Positions after it are shifted
+    pub(crate) fn remove(&self, item: &T, position: usize) {
+        let mut inner = self.lock();
+        let inner = &mut *inner;
+        inner.forget(item, position);
+        shift(inner.positions.values_mut(), position);
+        for secondary in inner.secondary.iter_mut() {
+            shift(secondary.entries.values_mut().flatten(), position);
+        }
+    }
+    // Remove the keys of the member at position
+    // Positions after it are not shifted
+    pub(crate) fn forget(&self, item: &T, position: usize) {
+        self.lock().forget(item, position)
+    }
+    // Copy of the indexes, that is not
+    // changed with the original one
+    pub(crate) fn snapshot(&self) -> Self {
+        let inner = self.lock();
+        IdIndex {
+            inner: Arc::new(Mutex::new(IndexInner {
+                id_key: inner.id_key,
+                positions: inner.positions.clone(),
+                folded: inner.folded.clone(),
+                secondary: inner
+                    .secondary
+                    .iter()
+                    .map(|s| Secondary {
+                        name: s.name.clone(),
+                        kind: s.kind,
+                        key: s.key.clone(),
+                        entries: s.entries.clone(),
+                    })
+                    .collect(),
+                stale: inner.stale,
+            })),
+        }
+    }
+    // Add a secondary index, built from the members
+    // An index with the same name is replaced.
+    pub(crate) fn add_index<'a, I>(
+        &self,
+        name: &str,
+        kind: IndexKind,
+        key: KeyFn<T>,
+        items: I,
+    ) -> PackResult<()>
+    where
+        I: Iterator<Item = &'a T>,
+        T: 'a,
+    {
+        let mut secondary = Secondary {
+            name: name.to_string(),
+            kind,
+            key,
+            entries: HashMap::new(),
+        };
+        for (position, item) in items.enumerate() {
+            secondary.check(None, item)?;
+            secondary.add((secondary.key)(item), position);
+        }
+        let mut inner = self.lock();
+        inner.secondary.retain(|s| s.name != name);
+        inner.secondary.push(secondary);
+        Ok(())
+    }
+    pub(crate) fn remove_index(&self, name: &str) -> bool {
+        let mut inner = self.lock();
+        let len = inner.secondary.len();
+        inner.secondary.retain(|s| s.name != name);
+        inner.secondary.len() != len
+    }
+    pub(crate) fn set_stale(&self) {
+        self.lock().stale = true;
+    }
+    pub(crate) fn is_stale(&self) -> bool {
+        self.lock().stale
+    }
+    // Build every index again from the members
+    // If an ID is duplicated, the first one wins
+    pub(crate) fn rebuild<'a, I>(&self, items: I)
+    where
+        I: Iterator<Item = &'a T>,
+        T: 'a,
+    {
+        let mut inner = self.lock();
+        let inner = &mut *inner;
+        inner.positions.clear();
+        inner.folded.clear();
+        for secondary in inner.secondary.iter_mut() {
+            secondary.entries.clear();
+        }
+        for (position, item) in items.enumerate() {
+            let key = (inner.id_key)(item);
+            inner.folded.entry(id::fold(&key)).or_insert(key.clone());
+            inner.positions.entry(key).or_insert(position);
+            for secondary in inner.secondary.iter_mut() {
+                secondary.add((secondary.key)(item), position);
+            }
+        }
+        inner.stale = false;
+    }
+    // Build every index again if it is stale
+    pub(crate) fn refresh<'a, I>(&self, items: I)
+    where
+        I: Iterator<Item = &'a T>,
+        T: 'a,
+    {
+        if self.is_stale() {
+            self.rebuild(items);
+        }
+    }
+    // Called before a member change is saved
+    // Returns PackError::IDTaken if the new ID belongs
+    // to an other member, PackError::IDCollision if its
+    // filename collides with an other one, or
+    // PackError::UniqueIndexViolation if the change
+    // breaks a unique index.
+    // A stale index still has the keys of the members,
+    // only their positions may be wrong.
+    pub(crate) fn check(&self, old: &T, new: &T) -> PackResult<()> {
+        self.lock().check(old, new)
+    }
+    // Called after a member change is saved
+    // Keys are updated even if the index is stale
+    pub(crate) fn changed(&self, old: &T, new: &T) {
+        self.lock().changed(old, new)
+    }
+    // Check a member change, save it, then update its keys
+    // Returns the result of save, None if it is skipped.
+    // If the change has new keys, then the indexes stay
+    // locked while it is saved, so an other member cannot
+    // take them in between.
+    pub(crate) fn change<F, R>(
+        &self,
+        old: &T,
+        new: &T,
+        save: F,
+    ) -> PackResult<Option<R>>
+    where
+        F: FnOnce() -> PackResult<Option<R>>,
+    {
+        let mut inner = self.lock();
+        inner.check(old, new)?;
+        if !inner.keys_changed(old, new) {
+            drop(inner);
+            return save();
+        }
+        let saved = save()?;
+        if saved.is_some() {
+            inner.changed(old, new);
+        }
+        Ok(saved)
+    }
+    fn lock(&self) -> MutexGuard<'_, IndexInner<T>> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Shift positions after a removed one
+fn shift<'a, I>(positions: I, removed: usize)
+where
+    I: Iterator<Item = &'a mut usize>,
+{
+    for position in positions {
+        if *position > removed {
+            *position -= 1;
+        }
+    }
+}
+
+// ID index key of a VecPack<T> member
+pub(crate) fn id_key<T>(item: &T) -> String
+where
+    T: VecPackMember,
+{
+    item.get_id().to_string()
+}
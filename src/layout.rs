@@ -0,0 +1,242 @@
+//! Directory layouts
+//!
+//! VecPack<T> keeps its member files flat in its directory, or sharded
+//! into hash-prefix subdirectories, so no directory holds more than a
+//! small part of a collection with millions of members. The layout and
+//! the ID strategy are recorded in a manifest dotfile of the VecPack
+//! directory; a directory without a manifest is flat. Shard directories
+//! are only read in the sharded layout, and a migration records it
+//! before the members move into them, so no member is left out by an
+//! interrupted migration.
+
+use crate::*;
+use std::io::Write;
+
+/// Name of the manifest file in the VecPack directory
+pub const MANIFEST_FILE: &str = ".manifest";
+
+/// Name of the directory in the VecPack directory
+/// where bad member files are moved by a lenient load
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+/// Max number of shard directory levels
+pub const MAX_SHARD_LEVELS: u8 = 4;
+
+// Prefix of the shard directory names
+// In encoded filenames '%' is always followed by two hex
+// digits, so a shard directory never has the name of a
+// member file, not even on case-insensitive filesystems.
+const SHARD_PREFIX: &str = "%_";
+
+// Version of the manifest format
+const MANIFEST_VERSION: u32 = 1;
+
+/// Directory layout of a VecPack<T>
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum Layout {
+    /// Every member file in the VecPack directory
+    #[default]
+    Flat,
+    /// Member files in nested shard directories, each level
+    /// named by two hex digits of the filename hash,
+    /// e.g. "%_3f/%_a2/<filename>" for 2 levels
+    Sharded { levels: u8 },
+}
+
+// Settings of a VecPack directory
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    version: u32,
+    pub(crate) layout: Layout,
+    // Name of the ID strategy, None for custom ones
+    id_strategy: Option<String>,
+}
+
+impl Manifest {
+    // Recorded ID strategy, or the default one
+    pub(crate) fn id_strategy(&self) -> id::IdStrategy {
+        self.id_strategy
+            .as_deref()
+            .and_then(id::IdStrategy::from_name)
+            .unwrap_or_default()
+    }
+}
+
+impl Layout {
+    /// Sharded layout
+    /// Levels are limited to 1..=MAX_SHARD_LEVELS
+    pub fn sharded(levels: u8) -> Self {
+        Layout::Sharded {
+            levels: levels.clamp(1, MAX_SHARD_LEVELS),
+        }
+    }
+    // File path of a VecPack member by its ID
+    pub(crate) fn member_path<I>(&self, dir: &Path, id: &I) -> PathBuf
+    where
+        I: fmt::Display + ?Sized,
+    {
+        let name = id::encode_filename(&id.to_string());
+        match self {
+            Layout::Flat => dir.join(name),
+            Layout::Sharded { levels } => {
+                let hash = format!("{:08x}", crc32fast::hash(name.as_bytes()));
+                let mut path = dir.to_path_buf();
+                let levels = (*levels).clamp(1, MAX_SHARD_LEVELS) as usize;
+                for level in 0..levels {
+                    path.push(format!(
+                        "{}{}",
+                        SHARD_PREFIX,
+                        &hash[level * 2..level * 2 + 2]
+                    ));
+                }
+                path.join(name)
+            }
+        }
+    }
+    // File path of a new VecPack member
+    // Shard directories are created if needed
+    pub(crate) fn new_member_path<I>(
+        &self,
+        dir: &Path,
+        id: &I,
+    ) -> PackResult<PathBuf>
+    where
+        I: fmt::Display + ?Sized,
+    {
+        let path = self.member_path(dir, id);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Ok(path)
+    }
+}
+
+// Manifest of the VecPack directory
+// Flat with the default ID strategy if there is no manifest
+pub(crate) fn read_manifest(dir: &Path) -> PackResult<Manifest> {
+    let path = dir.join(MANIFEST_FILE);
+    match std::fs::read(&path) {
+        Ok(bytes) => {
+            let manifest: Manifest = serde_json::from_slice(&bytes)?;
+            if manifest.version != MANIFEST_VERSION {
+                return Err(PackError::InternalError(format!(
+                    "Unknown VecPack manifest version {}. Path: {}",
+                    manifest.version,
+                    path.display()
+                )));
+            }
+            Ok(manifest)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Manifest {
+            version: MANIFEST_VERSION,
+            layout: Layout::Flat,
+            id_strategy: None,
+        }),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Record the layout and the ID strategy in the VecPack directory
+// Written to a temp file first, then renamed
+pub(crate) fn write_manifest(
+    dir: &Path,
+    layout: Layout,
+    id_strategy: &id::IdStrategy,
+) -> PackResult<()> {
+    let manifest = Manifest {
+        version: MANIFEST_VERSION,
+        layout,
+        id_strategy: id_strategy.name().map(|name| name.to_string()),
+    };
+    let tmp = dir.join(format!("{}.tmp", MANIFEST_FILE));
+    let mut file = std::fs::File::create(&tmp)?;
+    file.write_all(&serde_json::to_vec(&manifest)?)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, dir.join(MANIFEST_FILE))?;
+    Ok(())
+}
+
+// Member files of a VecPack directory
+// Dotfiles and other directories are skipped, shard
+// directories are only read in the sharded layout
+pub(crate) fn member_files(
+    dir: &Path,
+    layout: Layout,
+) -> PackResult<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            if layout != Layout::Flat && is_shard_dir(&path) {
+                files.extend(member_files(&path, layout)?);
+            }
+        } else if is_member_file(&path) {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+// Dotfiles of the VecPack directory, except
+// the manifest and the ID counter files
+pub(crate) fn dot_files(dir: &Path) -> PackResult<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let own = [MANIFEST_FILE, id::COUNTER_FILE]
+            .iter()
+            .any(|own| name.starts_with(own));
+        if name.starts_with('.') && !own && entry.file_type()?.is_file() {
+            files.push(entry.path());
+        }
+    }
+    Ok(files)
+}
+
+// Remove the empty shard directories
+// Returns true if dir is left empty
+pub(crate) fn remove_empty_dirs(dir: &Path) -> PackResult<bool> {
+    let mut empty = true;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir()
+            && is_shard_dir(&path)
+            && remove_empty_dirs(&path)?
+        {
+            std::fs::remove_dir(&path)?;
+        } else {
+            empty = false;
+        }
+    }
+    Ok(empty)
+}
+
+// Whether path is named like a shard directory
+fn is_shard_dir(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with(SHARD_PREFIX))
+}
+
+// Move a bad member file into the quarantine directory
+// An existing file with the same name is not replaced,
+// a numbered suffix is added instead.
+pub(crate) fn quarantine(dir: &Path, file: &Path) -> PackResult<PathBuf> {
+    let target_dir = dir.join(QUARANTINE_DIR);
+    std::fs::create_dir_all(&target_dir)?;
+    let name = file
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut target = target_dir.join(&name);
+    let mut n = 0;
+    while target.exists() {
+        n += 1;
+        target = target_dir.join(format!("{}.{}", name, n));
+    }
+    std::fs::rename(file, &target)?;
+    Ok(target)
+}
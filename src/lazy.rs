@@ -0,0 +1,180 @@
+//! Lazy loading
+//!
+//! LazyPack<T> only opens the packfile and checks its header when it
+//! is created. T is deserialized on the first access, and it can be
+//! evicted from memory later; the next access loads it again.
+
+use crate::*;
+use std::sync::OnceLock;
+
+/// LazyPack<T>
+/// Pack<T> that is loaded on its first access
+///
+/// ```rust,no_run
+/// use packman::lazy::LazyPack;
+/// # use std::path::PathBuf;
+/// let mut counter: LazyPack<u32> =
+///     LazyPack::open(PathBuf::from("data/counter"))?;
+/// // Deserialized here
+/// counter.update(|c| *c += 1)?;
+/// // Saved data is dropped from memory
+/// counter.evict()?;
+/// # Ok::<(), packman::PackError>(())
+/// ```
+pub struct LazyPack<T>
+where
+    T: Serialize + Sized + Clone,
+{
+    path: PathBuf,
+    // Version of the last load or save
+    version: u64,
+    // Applied to the Pack<T> when it is loaded
+    options: PackOptions,
+    pack: OnceLock<Pack<T>>,
+}
+
+impl<T> LazyPack<T>
+where
+    for<'de> T: Serialize + Deserialize<'de> + Sized + Clone,
+{
+    /// Open LazyPack<T> from Path
+    /// Only the packfile header is read and
+    /// checked, T is not deserialized.
+    pub fn open(path: PathBuf) -> PackResult<LazyPack<T>> {
+        LazyPack::open_with(path, PackOptions::default())
+    }
+    /// Open LazyPack<T> from Path in read-only mode
+    /// The loaded Pack<T> is read-only.
+    pub fn open_read_only(path: PathBuf) -> PackResult<LazyPack<T>> {
+        LazyPack::open_with(
+            path,
+            PackOptions {
+                read_only: true,
+                ..PackOptions::default()
+            },
+        )
+    }
+    pub(crate) fn open_with(
+        path: PathBuf,
+        options: PackOptions,
+    ) -> PackResult<LazyPack<T>> {
+        let version = fs::PackFile::open_read_only(&path)?.get_version();
+        Ok(LazyPack {
+            path,
+            version,
+            options,
+            pack: OnceLock::new(),
+        })
+    }
+    /// Loaded T
+    /// Deserializes T on the first call
+    pub fn get(&self) -> PackResult<&T> {
+        Ok(self.pack()?.unpack())
+    }
+    /// Loaded Pack<T>, to use its whole API
+    /// Deserializes T on the first call
+    pub fn pack(&self) -> PackResult<&Pack<T>> {
+        if let Some(pack) = self.pack.get() {
+            return Ok(pack);
+        }
+        let pack = self.load()?;
+        Ok(self.pack.get_or_init(|| pack))
+    }
+    /// Mutable loaded Pack<T>
+    /// Deserializes T on the first call
+    pub fn pack_mut(&mut self) -> PackResult<&mut Pack<T>> {
+        self.pack()?;
+        self.pack.get_mut().ok_or(PackError::ObjectNotFound)
+    }
+    /// Update T through closure, then save it
+    /// Deserializes T first if needed.
+    pub fn update<F, R>(&mut self, f: F) -> PackResult<R>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        self.pack_mut()?.update(f)
+    }
+    /// True if T is in memory
+    pub fn is_loaded(&self) -> bool {
+        self.pack.get().is_some()
+    }
+    /// Drop T from memory
+    /// Pending background save is written first. Returns
+    /// false if T is not loaded, or it has unsaved changes,
+    /// so it is kept.
+    pub fn evict(&mut self) -> PackResult<bool> {
+        match self.pack.get() {
+            Some(pack) => pack.flush()?,
+            None => return Ok(false),
+        }
+        match self.pack.take() {
+            Some(pack) if pack.is_dirty() => {
+                self.pack = OnceLock::from(pack);
+                Ok(false)
+            }
+            Some(pack) => {
+                self.version = pack.version();
+                self.options = pack.options.clone();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+    /// Packfile version of the last load or save
+    pub fn version(&self) -> u64 {
+        match self.pack.get() {
+            Some(pack) => pack.version(),
+            None => self.version,
+        }
+    }
+    /// Returns LazyPack<T>
+    /// &Path
+    pub fn get_path(&self) -> &Path {
+        self.path.as_path()
+    }
+    /// Loaded Pack<T>
+    /// Deserializes T if needed
+    pub fn into_pack(mut self) -> PackResult<Pack<T>> {
+        self.pack()?;
+        self.pack.take().ok_or(PackError::ObjectNotFound)
+    }
+    fn load(&self) -> PackResult<Pack<T>> {
+        let mut pack = Pack::load_from_path(self.path.clone())?;
+        pack.options = self.options.clone();
+        Ok(pack)
+    }
+}
+
+impl<T> From<Pack<T>> for LazyPack<T>
+where
+    T: Serialize + Sized + Clone,
+{
+    fn from(pack: Pack<T>) -> Self {
+        LazyPack {
+            path: pack.path.clone(),
+            version: pack.version(),
+            options: pack.options.clone(),
+            pack: OnceLock::from(pack),
+        }
+    }
+}
+
+impl<T> Deref for LazyPack<T>
+where
+    for<'de> T: Serialize + Deserialize<'de> + Sized + Clone,
+{
+    type Target = T;
+
+    // Panics if T cannot be loaded
+    // use get() to handle the error
+    fn deref(&self) -> &Self::Target {
+        match self.get() {
+            Ok(data) => data,
+            Err(err) => panic!(
+                "Cannot load pack. Path: {}, error: {}",
+                self.path.display(),
+                err
+            ),
+        }
+    }
+}
@@ -33,8 +33,25 @@ use std::fmt;
 use std::io;
 use std::ops::{Deref, DerefMut};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 pub mod fs;
+pub mod id;
+pub mod index;
+pub mod layout;
+pub mod lazy;
+pub mod load;
+pub mod observer;
+pub mod pool;
+pub mod query;
+pub mod shared;
+pub mod transaction;
+pub mod workspace;
+pub mod write_behind;
+
+pub use packman_derive::PackRepository;
 
 /// PackResult<T>
 ///
@@ -91,6 +108,43 @@ pub enum PackError {
     PckflDataError,
     BincodeError(String),
     JsonError(String),
+    /// Errors collected while loading or saving
+    /// the members of a PackRepository
+    /// (field name, error)
+    RepositoryError(Vec<(String, PackError)>),
+    /// When a packfile or a workspace registry
+    /// belongs to an other workspace
+    /// (expected, found)
+    WorkspaceMismatch(u64, Option<u64>),
+    /// When a conditional update finds that the
+    /// packfile was saved by someone else
+    /// (expected, found)
+    VersionConflict(u64, u64),
+    /// When the packfile has no
+    /// previous version to load
+    NoBackup,
+    /// When trying to write a pack
+    /// opened in read-only mode
+    ReadOnly,
+    /// When an insert or a save would give a key
+    /// of a unique index to a second VecPack member
+    /// (index name, key)
+    UniqueIndexViolation(String, String),
+    /// When the filename of a new VecPack member would
+    /// only differ in case from an other member's one
+    /// (new ID, taken ID)
+    IDCollision(String, String),
+    /// When a VecPack member file name does
+    /// not match the ID inside the file
+    /// (ID by filename, ID in file)
+    IDMismatch(String, String),
+    /// When a VecPack path exists
+    /// but it is not a directory
+    NotADirectory(String),
+    /// When a VecPack member file
+    /// cannot be loaded
+    /// (file path, error)
+    MemberLoadError(String, Box<PackError>),
 }
 
 impl From<Box<bincode::ErrorKind>> for PackError {
@@ -141,6 +195,46 @@ impl fmt::Display for PackError {
                 write!(f, "Packfile corrupted inodes")
             }
             PackError::PckflDataError => write!(f, "Packfile corrupted data"),
+            PackError::RepositoryError(errors) => {
+                write!(f, "Repository error:")?;
+                for (field, err) in errors {
+                    write!(f, " {}: {};", field, err)?;
+                }
+                Ok(())
+            }
+            PackError::WorkspaceMismatch(expected, found) => write!(
+                f,
+                "Workspace ID mismatch. Required {}, found {:?}",
+                expected, found
+            ),
+            PackError::VersionConflict(expected, found) => write!(
+                f,
+                "Packfile version conflict. Expected {}, found {}",
+                expected, found
+            ),
+            PackError::NoBackup => write!(f, "Packfile has no backup"),
+            PackError::ReadOnly => write!(f, "Pack is opened as read-only"),
+            PackError::UniqueIndexViolation(index, key) => write!(
+                f,
+                "Unique index violation. Index {}, key {}",
+                index, key
+            ),
+            PackError::IDCollision(id, taken) => write!(
+                f,
+                "VecPack ID {} collides with the filename of ID {}",
+                id, taken
+            ),
+            PackError::IDMismatch(name, id) => write!(
+                f,
+                "VecPack member file name mismatch. Filename ID {}, found {}",
+                name, id
+            ),
+            PackError::NotADirectory(path) => {
+                write!(f, "Given VecPack path is not a dir. Path: {}", path)
+            }
+            PackError::MemberLoadError(path, err) => {
+                write!(f, "Cannot load VecPack member file {}: {}", path, err)
+            }
         }
     }
 }
@@ -180,6 +274,46 @@ impl fmt::Debug for PackError {
                 write!(f, "Packfile corrupted inodes")
             }
             PackError::PckflDataError => write!(f, "Packfile corrupted data"),
+            PackError::RepositoryError(errors) => {
+                write!(f, "Repository error:")?;
+                for (field, err) in errors {
+                    write!(f, " {}: {};", field, err)?;
+                }
+                Ok(())
+            }
+            PackError::WorkspaceMismatch(expected, found) => write!(
+                f,
+                "Workspace ID mismatch. Required {}, found {:?}",
+                expected, found
+            ),
+            PackError::VersionConflict(expected, found) => write!(
+                f,
+                "Packfile version conflict. Expected {}, found {}",
+                expected, found
+            ),
+            PackError::NoBackup => write!(f, "Packfile has no backup"),
+            PackError::ReadOnly => write!(f, "Pack is opened as read-only"),
+            PackError::UniqueIndexViolation(index, key) => write!(
+                f,
+                "Unique index violation. Index {}, key {}",
+                index, key
+            ),
+            PackError::IDCollision(id, taken) => write!(
+                f,
+                "VecPack ID {} collides with the filename of ID {}",
+                id, taken
+            ),
+            PackError::IDMismatch(name, id) => write!(
+                f,
+                "VecPack member file name mismatch. Filename ID {}, found {}",
+                name, id
+            ),
+            PackError::NotADirectory(path) => {
+                write!(f, "Given VecPack path is not a dir. Path: {}", path)
+            }
+            PackError::MemberLoadError(path, err) => {
+                write!(f, "Cannot load VecPack member file {}: {}", path, err)
+            }
         }
     }
 }
@@ -190,17 +324,151 @@ impl From<io::Error> for PackError {
     }
 }
 
+/// Update Error type
+/// Error of Pack::try_update(), either
+/// the closure error or the save error
+pub enum UpdateError<E> {
+    /// Error returned by the update closure
+    /// T is rolled back, and not saved
+    User(E),
+    /// Error occured during save
+    /// T is rolled back
+    Pack(PackError),
+}
+
+impl<E> From<PackError> for UpdateError<E> {
+    fn from(err: PackError) -> Self {
+        UpdateError::Pack(err)
+    }
+}
+
+impl<E> fmt::Display for UpdateError<E>
+where
+    E: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UpdateError::User(err) => write!(f, "{}", err),
+            UpdateError::Pack(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl<E> fmt::Debug for UpdateError<E>
+where
+    E: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpdateError::User(err) => write!(f, "User error: {:?}", err),
+            UpdateError::Pack(err) => write!(f, "Pack error: {:?}", err),
+        }
+    }
+}
+
 /// Pack<T>
 /// Small FS layer around type T
 /// Pack is responsible to sync T to the filesystem.
+/// The packfile is kept open after the first save; if an
+/// other process writes it too, call reload() before the
+/// next save, or use update_if_version().
 pub struct Pack<T>
 where
     T: Serialize + Sized + Clone,
 {
     // id: u64,
-    // dirty: bool,
     data: T,
     path: PathBuf,
+    options: PackOptions,
+    save_state: SaveState,
+    observers: observer::Observers<T>,
+    // Listeners of the VecPack<T> this pack belongs to
+    parent_observers: Option<observer::Observers<T>>,
+    // Indexes of the VecPack<T> this pack belongs to
+    member_index: Option<index::IdIndex<T>>,
+    // ID of T if this pack is a VecPack<T> member
+    member_id: Option<fn(&T) -> String>,
+    // Directory and layout of the VecPack<T> this pack belongs to
+    member_layout: Option<(PathBuf, layout::Layout)>,
+    // Packfile kept open between saves
+    file: pool::FileSlot,
+}
+
+// Settings of a Pack<T>
+// VecPack<T> keeps one, and applies it to its members
+#[derive(Clone, Default)]
+struct PackOptions {
+    // Workspace ID written into the superblock
+    // when the underlying packfile is created
+    workspace_id: Option<u64>,
+    // Compare serialized data checksum with the last
+    // saved one, and skip save if nothing changed
+    compare_on_save: bool,
+    // What to do when the implicit save
+    // of a dropped PackGuard fails
+    drop_error_handler: DropErrorHandler,
+    // Background saving, if enabled
+    write_behind: Option<WriteBehindTarget>,
+    // Every write returns PackError::ReadOnly
+    read_only: bool,
+    // Limits the open packfiles of VecPack<T> members
+    file_pool: Option<pool::FilePool>,
+}
+
+// Write-behind worker and debounce
+// settings of a Pack<T>
+#[derive(Clone)]
+struct WriteBehindTarget {
+    handle: write_behind::WriteBehindHandle,
+    delay: Duration,
+    max_delay: Duration,
+}
+
+/// SaveStats
+/// Save counters of a Pack<T>
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SaveStats {
+    /// Number of writes to the packfile
+    pub saved: u64,
+    /// Number of skipped saves,
+    /// as data was not changed
+    pub skipped: u64,
+}
+
+// Save bookkeeping of a Pack<T>
+// Uses atomics and mutex, as Pack::save()
+// only has &self
+#[derive(Default)]
+struct SaveState {
+    dirty: AtomicBool,
+    checksum: Mutex<Option<u32>>,
+    saved: AtomicU64,
+    skipped: AtomicU64,
+    // Packfile version of the last load or save
+    version: AtomicU64,
+}
+
+impl SaveState {
+    fn checksum(&self) -> Option<u32> {
+        *self.checksum.lock().unwrap()
+    }
+    fn set_saved(&self, checksum: u32) {
+        *self.checksum.lock().unwrap() = Some(checksum);
+        self.dirty.store(false, Ordering::SeqCst);
+        self.saved.fetch_add(1, Ordering::SeqCst);
+    }
+    fn set_loaded(&self, checksum: u32, version: u64) {
+        *self.checksum.lock().unwrap() = Some(checksum);
+        self.dirty.store(false, Ordering::SeqCst);
+        self.set_version(version);
+    }
+    fn set_version(&self, version: u64) {
+        self.version.store(version, Ordering::SeqCst);
+    }
+    fn set_skipped(&self) {
+        self.dirty.store(false, Ordering::SeqCst);
+        self.skipped.fetch_add(1, Ordering::SeqCst);
+    }
 }
 
 /// PackGuard<'a, T>
@@ -208,13 +476,127 @@ where
 /// that implements Drop trait, and save T
 /// to the filesystem when PackGuard is dropped.
 ///
+/// Use commit() to get the save result, or abort()
+/// to discard the changes. If the implicit save fails
+/// during drop, then T is rolled back and the error
+/// is passed to the Pack DropErrorHandler.
+///
 /// Implements deref, deref_mut and drop
 pub struct PackGuard<'a, T>
 where
     T: Serialize + Sized + Clone,
 {
-    data: &'a mut T,
-    path: &'a PathBuf,
+    pack: &'a mut Pack<T>,
+    // True after the first mutable access
+    dirty: bool,
+    // Clone of T before the first mutable access
+    backup: Option<T>,
+    // True if committed or aborted
+    finished: bool,
+}
+
+/// DropErrorHandler
+/// Defines what happens when a PackGuard is dropped
+/// without commit, and its implicit save fails.
+/// The in-memory T is rolled back in every case.
+#[derive(Clone, Default)]
+pub enum DropErrorHandler {
+    /// Panic with the error
+    /// (only logs when the thread is already panicking)
+    Panic,
+    /// Log the error using the log crate
+    #[default]
+    Log,
+    /// Push the failed save into a RetryQueue
+    /// Only IO errors are pushed, others are logged,
+    /// as retrying them would not succeed.
+    Retry(RetryQueue),
+}
+
+/// FailedSave
+/// A save request that failed during PackGuard drop
+pub struct FailedSave {
+    pub path: PathBuf,
+    /// Serialized data that could not be saved
+    pub bytes: Vec<u8>,
+    pub workspace_id: Option<u64>,
+    /// Packfile version the failed save was based on
+    pub version: u64,
+    pub error: PackError,
+}
+
+/// RetryQueue
+/// Shared queue of failed saves. Cloning it
+/// gives an other handle to the same queue.
+///
+/// The in-memory data is rolled back when a save fails,
+/// so after a successful retry the Pack<T> is behind its
+/// packfile; load it again to see the retried data.
+/// A failed save is only written if the packfile is still
+/// at the version the save was based on, so a retry never
+/// overwrites a newer save.
+#[derive(Clone, Default)]
+pub struct RetryQueue {
+    inner: Arc<Mutex<Vec<FailedSave>>>,
+}
+
+impl RetryQueue {
+    /// New empty RetryQueue
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Number of failed saves in queue
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().len()
+    }
+    /// True if there is no failed save in queue
+    pub fn is_empty(&self) -> bool {
+        self.inner.lock().unwrap().is_empty()
+    }
+    /// Take all the failed saves out of the queue
+    pub fn take(&self) -> Vec<FailedSave> {
+        std::mem::take(&mut *self.inner.lock().unwrap())
+    }
+    /// Try to save every failed save again
+    /// Failed ones are kept in queue with their new error,
+    /// which is PackError::VersionConflict if the packfile
+    /// has been saved since. Returns the number of
+    /// successful saves.
+    pub fn retry(&self) -> usize {
+        let mut success = 0;
+        for mut failed in self.take() {
+            match retry_save(&failed) {
+                Ok(_) => success += 1,
+                Err(err) => {
+                    failed.error = err;
+                    self.push(failed);
+                }
+            }
+        }
+        success
+    }
+    fn push(&self, failed: FailedSave) {
+        self.inner.lock().unwrap().push(failed);
+    }
+}
+
+// Write a failed save if its packfile is still at the
+// version it was based on. A missing packfile is
+// created, as the failed save would have done.
+fn retry_save(failed: &FailedSave) -> PackResult<()> {
+    let existed = failed.path.is_file();
+    let mut pack_file = fs::PackFile::open_or_init(
+        &failed.path,
+        0,
+        None,
+        None,
+        failed.workspace_id,
+    )?;
+    let found = pack_file.get_version();
+    if existed && found != failed.version {
+        return Err(PackError::VersionConflict(failed.version, found));
+    }
+    pack_file.write_data(&failed.bytes)
 }
 
 /// VecPack<T>
@@ -229,6 +611,17 @@ where
 {
     data: Vec<Pack<T>>,
     path: PathBuf,
+    // Options applied to every member
+    options: PackOptions,
+    // Shared with every member
+    observers: observer::Observers<T>,
+    // Member ID and secondary indexes
+    // Shared with every member
+    index: index::IdIndex<T>,
+    // Used by insert_new()
+    id_strategy: id::IdStrategy,
+    // Recorded in the VecPack manifest
+    layout: layout::Layout,
 }
 
 /// This trait defines the requirements
@@ -243,18 +636,119 @@ pub trait TryFrom {
     type TryFrom: for<'de> Deserialize<'de> + Serialize + Default + Clone;
 }
 
-/// Save DATA OBJECT to its path
+/// PackRepository
+/// A struct whose fields are Pack<T> and VecPack<T> members
+/// living under a common root path. Use #[derive(PackRepository)]
+/// and mark each member with #[pack(path = "..")].
+///
+/// Errors are not returned one by one; every member is processed
+/// and all the failures are reported together in
+/// PackError::RepositoryError.
+pub trait PackRepository: Sized {
+    /// Load or init every member
+    /// under the given root path
+    fn load_or_init(root: PathBuf) -> PackResult<Self>;
+    /// Save every member to FS
+    fn save_all(&self) -> PackResult<()>;
+    /// Make sure every member is on the disk
+    fn flush(&self) -> PackResult<()> {
+        self.save_all()
+    }
+}
+
+/// RepositoryMember
+/// Types that can be a #[pack(path = "..")] field
+/// of a PackRepository. Implemented for Pack<T> and VecPack<T>.
+pub trait RepositoryMember: Sized {
+    /// Load or init member from root/path
+    fn load_member(root: &Path, path: &str) -> PackResult<Self>;
+    /// Save member to FS
+    fn save_member(&self) -> PackResult<()>;
+    /// Save member, and wait until
+    /// it is written to FS
+    fn flush_member(&self) -> PackResult<()> {
+        self.save_member()
+    }
+}
+
+/// Save DATA OBJECT bytes to its path
 /// Moved this logic into this separated private function
 /// as we use it from the Drop implementation and from save method.
-fn save_data_object<T>(path: &PathBuf, data: T) -> PackResult<()>
+/// Returns the new packfile version.
+fn save_data_object(
+    path: &PathBuf,
+    bytes: &[u8],
+    workspace_id: Option<u64>,
+) -> PackResult<u64> {
+    // TODO! Fix parameter flow
+    let mut pack_file =
+        fs::PackFile::open_or_init(&path, 0, None, None, workspace_id)?;
+    let version = pack_file.metadata().file_version;
+    pack_file.write_data(bytes)?;
+    Ok(version + 1)
+}
+
+/// Load DATA OBJECT from its path
+/// Returns the data, the checksum of its bytes
+/// and the packfile version.
+fn load_data_object<T>(path: &Path) -> PackResult<(T, u32, u64)>
+where
+    for<'de> T: Deserialize<'de>,
+{
+    let mut pack_file = fs::PackFile::open_read_only(path)?;
+    let bytes = pack_file.load_data()?;
+    match serde_json::from_slice::<T>(&bytes) {
+        Ok(t) => Ok((
+            t,
+            crc32fast::hash(&bytes),
+            pack_file.metadata().file_version,
+        )),
+        Err(err) => Err(PackError::DeserializeError(err.to_string())),
+    }
+}
+
+/// Error of a VecPack member file
+/// with the path of the file
+fn member_load_error(path: &Path, err: PackError) -> PackError {
+    PackError::MemberLoadError(path.display().to_string(), Box::new(err))
+}
+
+/// Member named by an ID starting with '.'
+/// A dotfile is only a member if it
+/// holds its own name as ID
+fn load_dot_member<T>(file: &Path) -> Option<Pack<T>>
+where
+    for<'de> T: VecPackMember + Deserialize<'de>,
+{
+    let name = file.file_name()?.to_string_lossy().to_string();
+    Pack::<T>::load_from_path(file.to_path_buf())
+        .ok()
+        .filter(|pack| pack.get_id().to_string() == name)
+}
+
+/// ID of a VecPack member as a string
+fn member_id<T: VecPackMember>(data: &T) -> String {
+    data.get_id().to_string()
+}
+
+/// True if the file is a VecPack member
+/// Dotfiles inside the VecPack directory
+/// (e.g. the ID counter) are not members,
+/// nor the files of dot directories
+fn is_member_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| !name.starts_with('.'))
+}
+
+/// Serialize DATA OBJECT
+/// into the bytes we store in packfiles
+fn serialize_data_object<T>(data: &T) -> PackResult<Vec<u8>>
 where
     T: Serialize,
 {
-    // TODO! Fix parameter flow
-    let mut pack_file = fs::PackFile::open_or_init(&path, 0, None, None, None)?;
-    // pack_file.write_data(&bincode::serialize(&data)?)?;
-    pack_file.write_data(&serde_json::to_string(&data)?.as_bytes())?;
-    Ok(())
+    // Ok(bincode::serialize(&data)?)
+    Ok(serde_json::to_vec(data)?)
 }
 
 #[derive(Serialize, Deserialize, Clone, Default)]
@@ -279,10 +773,7 @@ where
                 let data = Pack::<T::TryFrom>::load_from_path(path.clone())?
                     .into_inner()
                     .into();
-                let pack: Pack<T> = Pack {
-                    data: data,
-                    path: path,
-                };
+                let pack: Pack<T> = Pack::from_data(data, path);
                 pack.save()?;
                 Ok(pack)
             }
@@ -303,25 +794,48 @@ where
     }
 }
 
-impl<'a, T> Pack<T>
+impl<T> Pack<T>
 where
-    for<'de> T: Serialize + Deserialize<'de> + Default + Sized + Clone + 'a,
+    for<'de> T: Serialize + Deserialize<'de> + Default + Sized + Clone,
 {
     // New Pack<T>
     // Private function
     fn new(path: PathBuf) -> PackResult<Self> {
-        Ok(Pack {
-            data: T::default(),
+        Ok(Pack::from_data(T::default(), path))
+    }
+    /// Load or init Pack<T> from Path
+    /// If Path does not exist, then it tries to create;
+    /// Otherwise call Pack::load_from_path(Path).
+    pub fn load_or_init(path: PathBuf, file_id: &str) -> PackResult<Pack<T>> {
+        Pack::load_or_init_in_workspace(path, file_id, None)
+    }
+    // Load or init Pack<T> from Path
+    // new packfile is created with the given workspace ID
+    pub(crate) fn load_or_init_in_workspace(
+        path: PathBuf,
+        file_id: &str,
+        workspace_id: Option<u64>,
+    ) -> PackResult<Pack<T>> {
+        Pack::load_or_init_in_workspace_with(
             path,
-        })
+            file_id,
+            workspace_id,
+            T::default,
+        )
     }
+}
+
+impl<'a, T> Pack<T>
+where
+    for<'de> T: Serialize + Deserialize<'de> + Sized + Clone + 'a,
+{
     pub fn from_str(buffer: &str, path: PathBuf) -> PackResult<Pack<T>> {
         // match serde_yaml::from_str::<T>(&buffer) {
         //   Ok(t) => Ok(Pack { data: t, path }),
         //   Err(err) => Err(PackError::DeserializeError(err.to_string())),
         // }
         match serde_json::from_slice::<T>(&buffer.as_bytes()) {
-            Ok(t) => Ok(Pack { data: t, path }),
+            Ok(t) => Ok(Pack::from_data(t, path)),
             Err(err) => Err(PackError::DeserializeError(err.to_string())),
         }
     }
@@ -337,33 +851,103 @@ where
         // let metadata = std::fs::metadata(&path).expect("unable to read metadata");
         // let mut buffer = vec![0; metadata.len() as usize];
         // f.read(&mut buffer).expect("buffer overflow");
-        let mut pack_file = fs::PackFile::open(&path)?;
-        match serde_json::from_slice::<T>(&pack_file.load_data()?) {
-            Ok(t) => Ok(Pack { data: t, path }),
-            Err(err) => Err(PackError::DeserializeError(err.to_string())),
+        let (t, checksum, version) = load_data_object(&path)?;
+        let pack = Pack::from_data(t, path);
+        pack.save_state.set_loaded(checksum, version);
+        Ok(pack)
+    }
+    /// Load the previous stored version of T
+    /// from the packfile backup inode.
+    /// Returns PackError::NoBackup if there is none.
+    pub fn previous(&self) -> PackResult<T> {
+        self.flush()?;
+        let bytes = fs::PackFile::open_read_only(&self.path)?.load_backup()?;
+        serde_json::from_slice::<T>(&bytes)
+            .map_err(|err| PackError::DeserializeError(err.to_string()))
+    }
+    /// Rollback T to its previous stored version
+    /// The previous version is saved as a new write, so
+    /// the current version becomes the backup; calling
+    /// rollback() again undoes the rollback.
+    pub fn rollback(&mut self) -> PackResult<()> {
+        let previous = self.previous()?;
+        let backup = std::mem::replace(&mut self.data, previous);
+        if let Err(err) = self.save_changes(true, Some(&backup)) {
+            self.data = backup;
+            return Err(err);
         }
+        Ok(())
+    }
+    /// Load Pack<T> from Path in read-only mode
+    /// The packfile is never written; every save,
+    /// update or commit returns PackError::ReadOnly,
+    /// and guard changes are rolled back.
+    pub fn load_read_only(path: PathBuf) -> PackResult<Pack<T>> {
+        let mut pack = Pack::load_from_path(path)?;
+        pack.options.read_only = true;
+        Ok(pack)
+    }
+    /// Reload T from FS if the packfile has been
+    /// changed since the last load or save.
+    /// Returns true if T has been reloaded.
+    pub fn reload(&mut self) -> PackResult<bool> {
+        self.flush()?;
+        if !self.is_stale()? {
+            return Ok(false);
+        }
+        // Inodes of the open packfile are outdated
+        self.file.close();
+        let (t, checksum, version) = load_data_object(&self.path)?;
+        self.data = t;
+        self.save_state.set_loaded(checksum, version);
+        Ok(true)
     }
     /// Load or init Pack<T> from Path
-    /// If Path does not exist, then it tries to create;
-    /// Otherwise call Pack::load_from_path(Path).
-    pub fn load_or_init(
+    /// Same as Pack::load_or_init(), but a new packfile
+    /// is created with the data returned by init,
+    /// so T does not need to implement Default.
+    pub fn load_or_init_with<F>(
+        path: PathBuf,
+        file_id: &str,
+        init: F,
+    ) -> PackResult<Pack<T>>
+    where
+        F: FnOnce() -> T,
+    {
+        Pack::load_or_init_in_workspace_with(path, file_id, None, init)
+    }
+    // Load or init Pack<T> from Path
+    // new packfile is created from init with the given workspace ID
+    pub(crate) fn load_or_init_in_workspace_with<F>(
         mut path: PathBuf,
         file_id: &str,
-    ) -> PackResult<Pack<T>> {
+        workspace_id: Option<u64>,
+        init: F,
+    ) -> PackResult<Pack<T>>
+    where
+        F: FnOnce() -> T,
+    {
         if !path.exists() {
             std::fs::create_dir_all(&path)?;
         }
         path.push(&format!("{}", file_id));
         if !path.exists() {
-            Pack::<T>::new(path.clone())?.save()?;
+            let mut pack = Pack::from_data(init(), path.clone());
+            pack.options.workspace_id = workspace_id;
+            pack.save()?;
         }
-        Pack::load_from_path(path)
+        let mut pack = Pack::load_from_path(path)?;
+        pack.options.workspace_id = workspace_id;
+        Ok(pack)
     }
     /// Save Pack<T> manually
     /// to FS. Returns PackError if something
     /// wrong occures.
     pub fn save(&self) -> PackResult<()> {
-        save_data_object(&self.path, &self.data)
+        let kind =
+            self.write_data_object(serialize_data_object(&self.data)?)?;
+        self.notify(kind, None, Some(&self.data));
+        Ok(())
     }
     /// Update Pack<T>
     /// Tries to update T, if SUCCESS
@@ -374,12 +958,15 @@ where
     where
         F: FnOnce(&mut T) -> R,
     {
+        if self.options.read_only {
+            return Err(PackError::ReadOnly);
+        }
         // First clone data as a backup.
         let backup = self.data.clone();
         // Let's do the update process.
         let res = f(&mut self.data);
         // Try to save data to the FS
-        match self.save() {
+        match self.save_changes(true, Some(&backup)) {
             // If success, then return the update result(s)
             Ok(_) => Ok(res),
             // If there is error occured during
@@ -387,11 +974,109 @@ where
             Err(err) => {
                 // Then rollback data to the backup.
                 self.data = backup;
+                self.save_state.dirty.store(false, Ordering::SeqCst);
                 // Return error
                 Err(err)
             }
         }
     }
+    /// Try update Pack<T>
+    /// Same as update(), but the closure can fail.
+    /// If it returns Err, then T is rolled back to
+    /// backup, nothing is saved and the error is returned
+    /// as UpdateError::User. If save fails, then T is rolled
+    /// back and UpdateError::Pack is returned.
+    pub fn try_update<F, R, E>(&mut self, f: F) -> Result<R, UpdateError<E>>
+    where
+        F: FnOnce(&mut T) -> Result<R, E>,
+    {
+        if self.options.read_only {
+            return Err(UpdateError::Pack(PackError::ReadOnly));
+        }
+        let backup = self.data.clone();
+        let res = match f(&mut self.data) {
+            Ok(res) => res,
+            Err(err) => {
+                self.data = backup;
+                return Err(UpdateError::User(err));
+            }
+        };
+        match self.save_changes(true, Some(&backup)) {
+            Ok(_) => Ok(res),
+            Err(err) => {
+                self.data = backup;
+                self.save_state.dirty.store(false, Ordering::SeqCst);
+                Err(UpdateError::Pack(err))
+            }
+        }
+    }
+    /// Update Pack<T> only if its packfile
+    /// has not been saved since the given version.
+    /// Tries to update T, then saves it if the on-disk
+    /// version is still expected; otherwise rolls back T
+    /// and returns PackError::VersionConflict.
+    /// Always saves synchronously, pending
+    /// write-behind save is flushed first.
+    pub fn update_if_version<F, R>(
+        &mut self,
+        expected: u64,
+        f: F,
+    ) -> PackResult<R>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        if self.options.read_only {
+            return Err(PackError::ReadOnly);
+        }
+        self.flush()?;
+        let backup = self.data.clone();
+        let res = f(&mut self.data);
+        let mut moved_from = None;
+        let result = self
+            .check_id_change(Some(&backup), &self.data)
+            .and_then(|_| self.move_for_id(self.id_path(&self.data)))
+            .and_then(|from| {
+                moved_from = from;
+                serialize_data_object(&self.data)
+            })
+            .and_then(|bytes| {
+                let checksum = crc32fast::hash(&bytes);
+                let version = self.file.with(
+                    &self.path,
+                    self.options.workspace_id,
+                    self.options.file_pool.as_ref(),
+                    |pack_file| {
+                        let version = pack_file.get_version();
+                        if version != expected {
+                            return Err(PackError::VersionConflict(
+                                expected, version,
+                            ));
+                        }
+                        pack_file.write_data(&bytes)?;
+                        Ok(pack_file.get_version())
+                    },
+                )?;
+                self.save_state.set_version(version);
+                self.save_state.set_saved(checksum);
+                Ok(())
+            });
+        match result {
+            Ok(_) => {
+                self.rekey_id(Some(&backup));
+                self.notify(
+                    observer::ChangeKind::Saved,
+                    Some(&backup),
+                    Some(&self.data),
+                );
+                Ok(res)
+            }
+            Err(err) => {
+                self.move_back(moved_from);
+                self.data = backup;
+                Err(err)
+            }
+        }
+    }
     /// Get(Fn) -> R
     /// Access data through closure
     /// Unmutable data access
@@ -413,8 +1098,10 @@ where
     /// returns
     pub fn as_mut(&mut self) -> PackGuard<'_, T> {
         PackGuard {
-            data: &mut self.data,
-            path: &self.path,
+            pack: self,
+            dirty: false,
+            backup: None,
+            finished: false,
         }
     }
     pub fn into_inner(self) -> T {
@@ -425,6 +1112,355 @@ where
     }
 }
 
+impl<T> Pack<T>
+where
+    T: Serialize + Sized + Clone,
+{
+    // Create Pack<T> around an already existing T
+    // Private function
+    fn from_data(data: T, path: PathBuf) -> Self {
+        Pack {
+            data,
+            path,
+            options: PackOptions::default(),
+            save_state: SaveState::default(),
+            observers: observer::Observers::default(),
+            parent_observers: None,
+            member_index: None,
+            member_id: None,
+            member_layout: None,
+            file: pool::FileSlot::default(),
+        }
+    }
+    /// Set what to do when a PackGuard implicit
+    /// save fails during drop. Default is Log.
+    pub fn set_drop_error_handler(&mut self, handler: DropErrorHandler) {
+        self.options.drop_error_handler = handler;
+    }
+    /// True if Pack<T> is opened in read-only mode
+    pub fn is_read_only(&self) -> bool {
+        self.options.read_only
+    }
+    /// True if the in-memory data has changes
+    /// that are not saved to FS yet
+    pub fn is_dirty(&self) -> bool {
+        self.save_state.dirty.load(Ordering::SeqCst)
+    }
+    /// Returns the save and skipped save
+    /// counters of Pack<T>
+    pub fn save_stats(&self) -> SaveStats {
+        SaveStats {
+            saved: self.save_state.saved.load(Ordering::SeqCst),
+            skipped: self.save_state.skipped.load(Ordering::SeqCst),
+        }
+    }
+    /// Compare the serialized data checksum with the last saved
+    /// one, and skip the write (and the version bump) if they are
+    /// the same. Default is false; only changed flag is checked.
+    pub fn set_compare_on_save(&mut self, compare_on_save: bool) {
+        self.options.compare_on_save = compare_on_save;
+    }
+    /// Save in the background through a write-behind worker.
+    /// Saves are merged, and only the last one is written when
+    /// no new save came for delay, or max_delay elapsed since the
+    /// first unwritten one. Save errors are delivered through the
+    /// worker error channel.
+    pub fn enable_write_behind(
+        &mut self,
+        handle: &write_behind::WriteBehindHandle,
+        delay: Duration,
+        max_delay: Duration,
+    ) {
+        self.options.write_behind = Some(WriteBehindTarget {
+            handle: handle.clone(),
+            delay,
+            max_delay,
+        });
+    }
+    /// Flush pending background save,
+    /// then save synchronously again
+    pub fn disable_write_behind(&mut self) -> PackResult<()> {
+        self.flush()?;
+        self.options.write_behind = None;
+        Ok(())
+    }
+    /// Barrier: wait until the pending background
+    /// save of Pack<T> is written. No-op when
+    /// write-behind is not enabled.
+    pub fn flush(&self) -> PackResult<()> {
+        match &self.options.write_behind {
+            Some(target) => {
+                target.handle.flush(&self.path)?;
+                // Background saves do not report the new
+                // version, so we read it from the packfile
+                if let Ok(pack_file) = fs::PackFile::open_read_only(&self.path)
+                {
+                    self.save_state
+                        .set_version(pack_file.metadata().file_version);
+                }
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+    /// True if the packfile has been changed
+    /// by someone else since the last load or save.
+    /// When write-behind is enabled, call flush() first.
+    pub fn is_stale(&self) -> PackResult<bool> {
+        let pack_file = fs::PackFile::open_read_only(&self.path)?;
+        Ok(pack_file.metadata().file_version != self.version())
+    }
+    /// Packfile version of the last load or save
+    /// Use it with update_if_version(). When write-behind
+    /// is enabled, it is only refreshed by flush().
+    pub fn version(&self) -> u64 {
+        self.save_state.version.load(Ordering::SeqCst)
+    }
+    // Save data only if it has changed
+    // If changed is false, then the guard or the
+    // closure did not access data as mutable.
+    // Old is the data before the change, if known
+    fn save_changes(
+        &mut self,
+        changed: bool,
+        old: Option<&T>,
+    ) -> PackResult<()> {
+        if !changed {
+            self.save_state.set_skipped();
+            return Ok(());
+        }
+        self.check_id_change(old, &self.data)?;
+        // An ID change moves the member file first,
+        // and it is moved back if the save fails
+        let moved_from = self.move_for_id(self.id_path(&self.data))?;
+        self.save_state.dirty.store(true, Ordering::SeqCst);
+        let saved = serialize_data_object(&self.data)
+            .and_then(|bytes| self.save_bytes(bytes));
+        match saved {
+            Ok(Some(kind)) => {
+                self.rekey_id(old);
+                self.notify(kind, old, Some(&self.data));
+            }
+            Ok(None) => (),
+            Err(err) => {
+                self.move_back(moved_from);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+    // Path of a VecPack<T> member file by the ID of data
+    // None if this pack is not a member, or the
+    // file is already there
+    fn id_path(&self, data: &T) -> Option<PathBuf> {
+        let path = match (self.member_id, &self.member_layout) {
+            (Some(member_id), Some((dir, layout))) => {
+                layout.member_path(dir, &member_id(data))
+            }
+            _ => return None,
+        };
+        (path != self.path).then_some(path)
+    }
+    // Move the member file after an ID change
+    // Returns the old path if it is moved
+    fn move_for_id(
+        &mut self,
+        path: Option<PathBuf>,
+    ) -> PackResult<Option<PathBuf>> {
+        match path {
+            Some(path) => {
+                let from = self.path.clone();
+                self.move_to(path)?;
+                Ok(Some(from))
+            }
+            None => Ok(None),
+        }
+    }
+    // Move the member file back after a failed save
+    fn move_back(&mut self, from: Option<PathBuf>) {
+        if let Some(from) = from {
+            if let Err(err) = self.move_to(from) {
+                log::error!("Error while moving {:?} back: {}", self.path, err);
+            }
+        }
+    }
+    // Before a VecPack<T> member is saved, check whether
+    // its new ID or unique index keys are taken
+    fn check_id_change(&self, old: Option<&T>, new: &T) -> PackResult<()> {
+        match (&self.member_index, old) {
+            (Some(member_index), Some(old)) => member_index.check(old, new),
+            _ => Ok(()),
+        }
+    }
+    // After a VecPack<T> member is saved,
+    // re-key it in the indexes
+    fn rekey_id(&self, old: Option<&T>) {
+        if let (Some(member_index), Some(old)) = (&self.member_index, old) {
+            member_index.changed(old, &self.data);
+        }
+    }
+    // Save already serialized data, unless compare
+    // on save is enabled and the bytes are not changed
+    // Returns the change kind, or None if save is skipped
+    fn save_bytes(
+        &self,
+        bytes: Vec<u8>,
+    ) -> PackResult<Option<observer::ChangeKind>> {
+        if self.options.compare_on_save
+            && self.save_state.checksum() == Some(crc32fast::hash(&bytes))
+        {
+            self.save_state.set_skipped();
+            return Ok(None);
+        }
+        self.write_data_object(bytes).map(Some)
+    }
+    // Notify the listeners of Pack<T>, and
+    // the listeners of its VecPack<T>
+    fn notify(
+        &self,
+        kind: observer::ChangeKind,
+        old: Option<&T>,
+        new: Option<&T>,
+    ) {
+        self.notice(kind, new.or(old)).send(old, new);
+    }
+    // Listeners of a change of Pack<T>, to notify
+    // later. Data is the value after the change, or
+    // the last value if the pack is removed.
+    fn notice(
+        &self,
+        kind: observer::ChangeKind,
+        data: Option<&T>,
+    ) -> observer::Notice<T> {
+        // Member files are named by the encoded ID, and
+        // are only renamed after an ID change is saved
+        let id = match (self.member_id, data) {
+            (Some(member_id), Some(data)) => member_id(data),
+            _ => self
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        };
+        let observers = std::iter::once(self.observers.clone())
+            .chain(self.parent_observers.clone())
+            .collect();
+        observer::Notice::new(kind, id, self.version(), observers)
+    }
+    // Pass a failed implicit save
+    // to the drop error handler
+    fn report_drop_error(&self, failed: FailedSave) {
+        match &self.options.drop_error_handler {
+            DropErrorHandler::Panic if !std::thread::panicking() => {
+                panic!("Error while saving {:?}: {}", failed.path, failed.error)
+            }
+            DropErrorHandler::Retry(queue)
+                if matches!(failed.error, PackError::IOError(_)) =>
+            {
+                queue.push(failed)
+            }
+            _ => log::error!(
+                "Error while saving {:?}: {}",
+                failed.path,
+                failed.error
+            ),
+        }
+    }
+    // Write serialized data to FS, or hand it
+    // over to the write-behind worker;
+    // then update save bookkeeping
+    // Returns ChangeKind::Queued if it is handed over
+    fn write_data_object(
+        &self,
+        bytes: Vec<u8>,
+    ) -> PackResult<observer::ChangeKind> {
+        if self.options.read_only {
+            return Err(PackError::ReadOnly);
+        }
+        self.save_state.dirty.store(true, Ordering::SeqCst);
+        let checksum = crc32fast::hash(&bytes);
+        let workspace_id = self.options.workspace_id;
+        let kind = match &self.options.write_behind {
+            Some(target) => {
+                // The worker writes the packfile by its path
+                self.file.close();
+                // If the worker is already stopped,
+                // then we save synchronously
+                match target.handle.save(
+                    &self.path,
+                    bytes,
+                    workspace_id,
+                    target.delay,
+                    target.max_delay,
+                ) {
+                    Ok(()) => observer::ChangeKind::Queued,
+                    Err(bytes) => {
+                        let version = self.write_file(&bytes)?;
+                        self.save_state.set_version(version);
+                        observer::ChangeKind::Saved
+                    }
+                }
+            }
+            None => {
+                let version = self.write_file(&bytes)?;
+                self.save_state.set_version(version);
+                observer::ChangeKind::Saved
+            }
+        };
+        self.save_state.set_saved(checksum);
+        Ok(kind)
+    }
+    // Write bytes into the open packfile
+    // Returns the new packfile version
+    fn write_file(&self, bytes: &[u8]) -> PackResult<u64> {
+        self.file.with(
+            &self.path,
+            self.options.workspace_id,
+            self.options.file_pool.as_ref(),
+            |pack_file| {
+                pack_file.write_data(bytes)?;
+                Ok(pack_file.get_version())
+            },
+        )
+    }
+    /// Delete Pack<T> from FS
+    /// and returns its data
+    pub fn delete(self) -> PackResult<T> {
+        if self.options.read_only {
+            return Err(PackError::ReadOnly);
+        }
+        // Pending background save would create it again
+        self.flush()?;
+        self.file.close();
+        fs::remove_file(&self.path)?;
+        self.notify(observer::ChangeKind::Removed, Some(&self.data), None);
+        Ok(self.data)
+    }
+    /// Move Pack<T> packfile to a new path
+    /// Returns PackError::IDTaken if path already exists.
+    /// Within one filesystem the packfile is renamed, so it
+    /// is never at both paths; between filesystems it is
+    /// copied, then removed.
+    pub fn move_to(&mut self, path: PathBuf) -> PackResult<()> {
+        if self.options.read_only {
+            return Err(PackError::ReadOnly);
+        }
+        // Pending background save would create
+        // the old path again
+        self.flush()?;
+        fs::move_file(&self.path, &path)?;
+        // Moved packfile could be a copy
+        self.file.close();
+        self.path = path;
+        Ok(())
+    }
+    /// Returns Pack<T>
+    /// &Path
+    pub fn get_path(&self) -> &Path {
+        self.path.as_path()
+    }
+}
+
 impl<T> Deref for Pack<T>
 where
     T: Serialize + Sized + Clone,
@@ -436,6 +1472,31 @@ where
     }
 }
 
+impl<T> RepositoryMember for Pack<T>
+where
+    for<'de> T: Serialize + Deserialize<'de> + Default + Sized + Clone,
+{
+    fn load_member(root: &Path, path: &str) -> PackResult<Self> {
+        let path = root.join(path.trim_start_matches('/'));
+        let file_id = match path.file_name().and_then(|n| n.to_str()) {
+            Some(file_id) => file_id.to_string(),
+            None => return Err(PackError::PathNotFound),
+        };
+        let dir = match path.parent() {
+            Some(dir) => dir.to_path_buf(),
+            None => return Err(PackError::PathNotFound),
+        };
+        Pack::load_or_init(dir, &file_id)
+    }
+    fn save_member(&self) -> PackResult<()> {
+        self.save()
+    }
+    fn flush_member(&self) -> PackResult<()> {
+        self.save()?;
+        self.flush()
+    }
+}
+
 impl<'a, T> Deref for PackGuard<'a, T>
 where
     T: Serialize + Sized + Clone,
@@ -443,7 +1504,7 @@ where
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        &self.data
+        &self.pack.data
     }
 }
 
@@ -452,7 +1513,8 @@ where
     T: Serialize + Sized + Clone,
 {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.data
+        self.touch();
+        &mut self.pack.data
     }
 }
 
@@ -461,13 +1523,62 @@ where
     T: Serialize + Sized + Clone,
 {
     fn drop(&mut self) {
-        // TODO: VERY IMPORTANT
-        // Implement error LOGGING!
+        if self.finished {
+            return;
+        }
         // This auto save during drop cannot return PackError,
-        // we have two options:
-        //  - Panic(),
-        //  - & | error log
-        let _ = save_data_object(&self.path, &self.data);
+        // so we pass it to the drop error handler.
+        if let Err(err) =
+            self.pack.save_changes(self.dirty, self.backup.as_ref())
+        {
+            let failed = FailedSave {
+                path: self.pack.path.clone(),
+                bytes: serialize_data_object(&self.pack.data)
+                    .unwrap_or_default(),
+                workspace_id: self.pack.options.workspace_id,
+                version: self.pack.version(),
+                error: err,
+            };
+            self.rollback();
+            self.pack.report_drop_error(failed);
+        }
+    }
+}
+
+impl<'a, T> PackGuard<'a, T>
+where
+    T: Serialize + Sized + Clone,
+{
+    /// Save changes and return the save result
+    /// If save fails, then T is rolled back.
+    pub fn commit(mut self) -> PackResult<()> {
+        self.finished = true;
+        let res = self.pack.save_changes(self.dirty, self.backup.as_ref());
+        if res.is_err() {
+            self.rollback();
+        }
+        res
+    }
+    /// Discard changes without saving
+    pub fn abort(mut self) {
+        self.finished = true;
+        self.rollback();
+    }
+    // Mark guard as dirty, and create backup
+    // at the first mutable access
+    fn touch(&mut self) {
+        if !self.dirty {
+            self.dirty = true;
+            self.backup = Some(self.pack.data.clone());
+        }
+    }
+    // Restore T from backup, if there is any
+    fn rollback(&mut self) {
+        if let Some(backup) = self.backup.take() {
+            self.pack.data = backup;
+        }
+        self.dirty = false;
+        self.pack.save_state.dirty.store(false, Ordering::SeqCst);
     }
 }
 
@@ -495,85 +1606,50 @@ where
         + std::convert::From<<T as TryFrom>::TryFrom>,
 {
     pub fn try_load_or_init(path: PathBuf) -> PackResult<VecPack<T>> {
-        // If path is a file
-        // then panic!
-        if path.is_file() {
-            panic!(
-                "Given VecPack path is not a dir. Path: {}",
-                &path.to_str().unwrap()
-            );
-        }
-        // If path does not exist,
-        // then we create it.
-        if !path.exists() {
-            std::fs::create_dir_all(&path)?;
-        }
-        // Result empty VecPack<T>
-        let mut result: VecPack<T> = VecPack::new(path.clone())?;
-        // First collect all
-        // the file names from path
-        std::fs::read_dir(path.clone())?
-            .filter_map(|file| {
-                file.ok().and_then(|e| {
-                    e.path().file_name().and_then(|n| {
-                        n.to_str().map(|s| {
-                            let mut p = path.clone();
-                            p.push(s);
-                            p
-                        })
-                    })
-                })
-            })
-            .collect::<Vec<PathBuf>>()
-            // Then iter over path vector
-            // and try to read and deserialize
-            // them.
-            .iter()
-            .for_each(|path| {
-                // Add deserialized T to VecPack<T>
-                result
-                    .insert_pack(
-                        // Create Pack<T> from T
-                        Pack::<T>::try_load_from_path(path.clone()).expect(
-                            &format!(
-                                "Cannot deserialize file with ID: {}",
-                                (&path).to_str().unwrap()
-                            ),
-                        ),
-                    )
-                    .expect(&format!(
-                        "Error while adding file to VecPack with ID: {}",
-                        (&path).to_str().unwrap()
-                    ));
-            });
-        Ok(result)
+        VecPack::load_members(path, false, Pack::try_load_from_path)
+            .map(|(result, _)| result)
+    }
+    /// Try load or init VecPack<T> in lenient mode
+    /// Same as VecPack::load_or_init_lenient(), but
+    /// member files are loaded by try_load_from_path().
+    pub fn try_load_or_init_lenient(
+        path: PathBuf,
+    ) -> PackResult<(VecPack<T>, Vec<load::LoadFailure>)> {
+        VecPack::load_members(path, true, Pack::try_load_from_path)
     }
 }
 
 impl<T> VecPack<T>
 where
-    for<'de> T: VecPackMember + Deserialize<'de> + Default,
+    for<'de> T: VecPackMember + Deserialize<'de>,
 {
-    // TODO: Check FS operations. What if path is a file?
     /// New VecPack<T>
     /// Requires a PathBuf and returns an empty VecPack<T>
+    /// Returns PackError::NotADirectory if path is a file.
     pub fn new(path: PathBuf) -> PackResult<VecPack<T>> {
         // Check whether path is a dir, or a file
-        // If file, then panic!
         if path.is_file() {
-            panic!(
-                "Given VecPack path is not a dir. Path: {}",
-                &path.to_str().unwrap()
-            );
+            return Err(PackError::NotADirectory(path.display().to_string()));
         }
         // If path does not exist,
         // then create it!
         if !path.exists() {
             std::fs::create_dir_all(&path)?;
         }
+        let manifest = layout::read_manifest(&path)?;
         // Create an empty VecPack<T>
         Ok(VecPack {
             data: Vec::new(),
+            options: PackOptions {
+                file_pool: Some(pool::FilePool::new(
+                    pool::DEFAULT_MAX_OPEN_FILES,
+                )),
+                ..PackOptions::default()
+            },
+            observers: observer::Observers::default(),
+            index: index::IdIndex::new(index::id_key::<T>),
+            id_strategy: manifest.id_strategy(),
+            layout: manifest.layout,
             path,
         })
     }
@@ -582,15 +1658,35 @@ where
     /// then we create it, then loads all the files,
     /// and tries to deserialize them.
     /// If a file cannot be read, or cannot be deserialized
-    /// then we panic!
+    /// then returns PackError::MemberLoadError. Use
+    /// VecPack::load_or_init_lenient() to skip the bad files,
+    /// and VecPack::migrate_filenames() to rename the files
+    /// whose name does not match the ID inside.
     pub fn load_or_init(path: PathBuf) -> PackResult<VecPack<T>> {
+        VecPack::load_members(path, false, Pack::load_from_path)
+            .map(|(result, _)| result)
+    }
+    /// Load or init VecPack<T> in lenient mode
+    /// Same as VecPack::load_or_init(), but the files that
+    /// cannot be loaded are returned as LoadFailures. Bad
+    /// files are moved into the quarantine directory, valid
+    /// members with a conflicting ID are left in place.
+    pub fn load_or_init_lenient(
+        path: PathBuf,
+    ) -> PackResult<(VecPack<T>, Vec<load::LoadFailure>)> {
+        VecPack::load_members(path, true, Pack::load_from_path)
+    }
+    // Load or init VecPack<T> by a given Path
+    // using the given member file loader
+    fn load_members(
+        path: PathBuf,
+        lenient: bool,
+        load: fn(PathBuf) -> PackResult<Pack<T>>,
+    ) -> PackResult<(VecPack<T>, Vec<load::LoadFailure>)> {
         // If path is a file
-        // then panic!
+        // then return error
         if path.is_file() {
-            panic!(
-                "Given VecPack path is not a dir. Path: {}",
-                &path.to_str().unwrap()
-            );
+            return Err(PackError::NotADirectory(path.display().to_string()));
         }
         // If path does not exist,
         // then we create it.
@@ -599,62 +1695,209 @@ where
         }
         // Result empty VecPack<T>
         let mut result: VecPack<T> = VecPack::new(path.clone())?;
-        // First collect all
-        // the file names from path
-        std::fs::read_dir(path.clone())?
-            .filter_map(|file| {
-                file.ok().and_then(|e| {
-                    e.path().file_name().and_then(|n| {
-                        n.to_str().map(|s| {
-                            let mut p = path.clone();
-                            p.push(s);
-                            p
-                        })
-                    })
-                })
-            })
-            .collect::<Vec<PathBuf>>()
-            // Then iter over path vector
-            // and try to read and deserialize
-            // them.
-            .iter()
-            .for_each(|path| {
-                // Add deserialized T to VecPack<T>
-                result
-                    .insert_pack(
-                        // Create Pack<T> from T
-                        Pack::<T>::load_from_path(path.clone()).expect(
-                            &format!(
-                                "Cannot deserialize file with ID: {}",
-                                (&path).to_str().unwrap()
-                            ),
-                        ),
-                    )
-                    .expect(&format!(
-                        "Error while adding file to VecPack with ID: {}",
-                        (&path).to_str().unwrap()
-                    ));
-            });
+        let mut failures = Vec::new();
+        // Load every member file
+        for file in layout::member_files(&path, result.layout)? {
+            // Add deserialized T to VecPack<T>
+            result.insert_loaded(
+                &file,
+                load(file.clone()),
+                lenient,
+                &mut failures,
+            )?;
+        }
+        result.check_dot_files(lenient, &mut failures)?;
+        Ok((result, failures))
+    }
+    /// Load or init VecPack by a given Path
+    /// Same as VecPack::load_or_init(), but if path does
+    /// not exist, then the members returned by init are
+    /// inserted into the new VecPack<T>.
+    pub fn load_or_init_with<F>(
+        path: PathBuf,
+        init: F,
+    ) -> PackResult<VecPack<T>>
+    where
+        F: FnOnce() -> Vec<T>,
+    {
+        let is_new = !path.exists();
+        let mut result = VecPack::load_or_init(path)?;
+        if is_new {
+            for item in init() {
+                result.insert(item)?;
+            }
+        }
         Ok(result)
     }
+    /// Load VecPack<T> from Path in read-only mode
+    /// Path must be an existing directory. Every member is
+    /// loaded as a read-only Pack<T>, and insert or remove
+    /// returns PackError::ReadOnly.
+    pub fn load_read_only(path: PathBuf) -> PackResult<VecPack<T>> {
+        if !path.is_dir() {
+            return Err(PackError::PathNotFound);
+        }
+        let manifest = layout::read_manifest(&path)?;
+        let mut result: VecPack<T> = VecPack {
+            data: Vec::new(),
+            path: path.clone(),
+            options: PackOptions::default(),
+            observers: observer::Observers::default(),
+            index: index::IdIndex::new(index::id_key::<T>),
+            id_strategy: manifest.id_strategy(),
+            layout: manifest.layout,
+        };
+        for entry_path in layout::member_files(&path, result.layout)? {
+            let pack =
+                result.check_member(Pack::load_from_path(entry_path)?)?;
+            result.insert_pack(pack)?;
+        }
+        result.check_dot_files(false, &mut Vec::new())?;
+        result.options.read_only = true;
+        result.apply_options();
+        Ok(result)
+    }
+    // Load or init VecPack<T> from Path
+    // new member packfiles are created with the given workspace ID
+    pub(crate) fn load_or_init_in_workspace(
+        path: PathBuf,
+        workspace_id: Option<u64>,
+    ) -> PackResult<VecPack<T>> {
+        let mut result = VecPack::load_or_init(path)?;
+        result.options.workspace_id = workspace_id;
+        result.apply_options();
+        Ok(result)
+    }
+    /// Rename the member files to their encoded IDs
+    /// Loading fails with PackError::IDMismatch on files named
+    /// before IDs were encoded, or left by an interrupted ID
+    /// change. This renames each of them to the file of the ID
+    /// inside. Returns the number of renamed files, or
+    /// PackError::IDMismatch if the file of the ID exists.
+    /// Files which cannot be loaded are left to the next load.
+    pub fn migrate_filenames(path: PathBuf) -> PackResult<usize> {
+        let layout = layout::read_manifest(&path)?.layout;
+        let files = layout::member_files(&path, layout)?;
+        let dot_files = layout::dot_files(&path)?;
+        let mut renamed = 0;
+        for (file, is_dot) in files
+            .into_iter()
+            .map(|file| (file, false))
+            .chain(dot_files.into_iter().map(|file| (file, true)))
+        {
+            let pack = match is_dot {
+                true => load_dot_member::<T>(&file),
+                false => Pack::<T>::load_from_path(file).ok(),
+            };
+            let mut pack = match pack {
+                Some(pack) => pack,
+                None => continue,
+            };
+            let id = pack.get_id().to_string();
+            let name = pack.path.file_name().unwrap_or_default();
+            let name = name.to_string_lossy().to_string();
+            if id::decode_filename(&name).as_ref() == Some(&id) {
+                continue;
+            }
+            if layout.member_path(&path, &id).exists() {
+                return Err(member_load_error(
+                    &pack.path,
+                    PackError::IDMismatch(name, id),
+                ));
+            }
+            pack.move_to(layout.new_member_path(&path, &id)?)?;
+            renamed += 1;
+        }
+        Ok(renamed)
+    }
     /// Insert a new T to VecPack<T>
     /// Only if ID is not taken
     pub fn insert(&mut self, item: T) -> PackResult<()> {
+        if self.options.read_only {
+            return Err(PackError::ReadOnly);
+        }
         // Check if ID whether available
         if !&self.check_id_available(item.get_id()) {
             return Err(PackError::IDTaken);
         }
-        // TODO: Move file name creation to a central place!
-        let mut p = (&self.path).clone();
-        p.push(&format!("{}", item.get_id()));
-        let p = Pack {
-            data: item,
-            path: p,
-        };
+        self.refresh_index();
+        self.index.check_insert(&item)?;
+        let p = self.layout.new_member_path(&self.path, item.get_id())?;
+        let mut p = Pack::from_data(item, p);
+        p.options = self.options.clone();
         p.save()?;
-        self.data.push(p);
+        self.adopt(&mut p);
+        p.notify(observer::ChangeKind::Inserted, None, Some(&p.data));
+        self.push(p);
+        Ok(())
+    }
+    /// Insert a new T with a generated ID
+    /// The ID is generated by the IdStrategy of VecPack<T>
+    /// until it is not taken, then it is passed to the closure
+    /// which creates T. Returns the inserted Pack<T>.
+    ///
+    /// ```rust,no_run
+    /// # use packman::*;
+    /// # use serde::{Deserialize, Serialize};
+    /// # use std::path::PathBuf;
+    /// # #[derive(Serialize, Deserialize, Clone)]
+    /// # struct Todo { id: String, title: String }
+    /// # impl VecPackMember for Todo {
+    /// #     type Out = String;
+    /// #     fn get_id(&self) -> &String { &self.id }
+    /// # }
+    /// let mut todos: VecPack<Todo> =
+    ///     VecPack::load_or_init(PathBuf::from("data/todos"))?;
+    /// let todo = todos.insert_new(|id| Todo {
+    ///     id,
+    ///     title: "Buy milk".to_string(),
+    /// })?;
+    /// # Ok::<(), PackError>(())
+    /// ```
+    pub fn insert_new<F>(&mut self, f: F) -> PackResult<&mut Pack<T>>
+    where
+        F: FnOnce(String) -> T,
+    {
+        if self.options.read_only {
+            return Err(PackError::ReadOnly);
+        }
+        self.refresh_index();
+        // The counter can meet IDs inserted by hand,
+        // but it passes all of them at most
+        let mut id = None;
+        for _ in 0..self.data.len() + 8 {
+            let candidate = self.id_strategy.generate(&self.path)?;
+            if self.index.is_available(&candidate)
+                && !self.layout.member_path(&self.path, &candidate).exists()
+            {
+                id = Some(candidate);
+                break;
+            }
+        }
+        let id = id.ok_or(PackError::IDTaken)?;
+        self.insert(f(id))?;
+        self.data.last_mut().ok_or(PackError::ObjectNotFound)
+    }
+    /// Set the ID strategy of insert_new()
+    /// Default is IdStrategy::NanoId
+    /// It is recorded in the manifest, so the next load
+    /// uses it too; except IdStrategy::Custom, which must
+    /// be set again after every load.
+    pub fn set_id_strategy(
+        &mut self,
+        strategy: id::IdStrategy,
+    ) -> PackResult<()> {
+        if self.options.read_only {
+            return Err(PackError::ReadOnly);
+        }
+        layout::write_manifest(&self.path, self.layout, &strategy)?;
+        self.id_strategy = strategy;
         Ok(())
     }
+    /// ID strategy of insert_new()
+    pub fn id_strategy(&self) -> &id::IdStrategy {
+        &self.id_strategy
+    }
     // pub fn remove_by_id(&mut self, id: &str) -> PackResult<()> {
     //     match self.iter().position(|i| i.get_id() == id) {
     //         Some(p) => {
@@ -665,32 +1908,75 @@ where
     // }
     /// Insert Pack<T> to VecPack<T>
     /// Only if ID is not taken
-    pub fn insert_pack(&mut self, item: Pack<T>) -> PackResult<()> {
+    pub fn insert_pack(&mut self, mut item: Pack<T>) -> PackResult<()> {
+        if self.options.read_only {
+            return Err(PackError::ReadOnly);
+        }
         if !&self.check_id_available(item.get_id()) {
             return Err(PackError::IDTaken);
         }
-        self.data.push(item);
+        self.refresh_index();
+        self.index.check_insert(&item.data)?;
+        item.options = self.options.clone();
+        self.adopt(&mut item);
+        self.push(item);
         Ok(())
     }
     pub fn remove_pack(
         &mut self,
         id: &<T as VecPackMember>::Out,
     ) -> PackResult<T> {
-        if let Some(index) = self.data.iter().position(|x| x.get_id() == id) {
-            // TODO! implement packman::fs::remove_file(&path) instead and manage auto backup
-            std::fs::remove_file(&self.data[index].path)?;
-            return Ok(self.data.remove(index).into_inner());
+        if self.options.read_only {
+            return Err(PackError::ReadOnly);
+        }
+        if let Some(index) = self.position(id) {
+            // TODO! manage auto backup
+            self.data[index].flush()?;
+            fs::remove_file(&self.data[index].path)?;
+            let item = self.take(index);
+            item.notify(observer::ChangeKind::Removed, Some(&item.data), None);
+            return Ok(item.into_inner());
         }
         Err(PackError::ObjectNotFound)
     }
+    /// Move member by ID into an other VecPack<T>
+    /// Returns PackError::IDTaken if ID is taken
+    /// in the other VecPack<T>. The member file is moved
+    /// as by Pack::move_to().
+    pub fn move_item(
+        &mut self,
+        id: &<T as VecPackMember>::Out,
+        other: &mut VecPack<T>,
+    ) -> PackResult<()> {
+        if self.options.read_only || other.options.read_only {
+            return Err(PackError::ReadOnly);
+        }
+        if !other.check_id_available(id) {
+            return Err(PackError::IDTaken);
+        }
+        let index = match self.position(id) {
+            Some(index) => index,
+            None => return Err(PackError::ObjectNotFound),
+        };
+        other.refresh_index();
+        other.index.check_insert(&self.data[index].data)?;
+        self.data[index].move_to(other.layout.member_path(&other.path, id))?;
+        let mut item = self.take(index);
+        item.notify(observer::ChangeKind::Removed, Some(&item.data), None);
+        item.options = other.options.clone();
+        other.adopt(&mut item);
+        item.notify(observer::ChangeKind::Inserted, None, Some(&item.data));
+        other.push(item);
+        Ok(())
+    }
     /// Find ID and returns &Pack<T>
     /// as an unmutable reference
     pub fn find_id(
         &self,
         id: &<T as VecPackMember>::Out,
     ) -> PackResult<&Pack<T>> {
-        match self.iter().position(|i| i.get_id() == id) {
-            Some(p) => Ok(&self.get(p).unwrap()),
+        match self.position(id) {
+            Some(p) => Ok(&self.data[p]),
             None => Err(PackError::ObjectNotFound),
         }
     }
@@ -700,8 +1986,8 @@ where
         &mut self,
         id: &<T as VecPackMember>::Out,
     ) -> PackResult<&mut Pack<T>> {
-        match &mut self.into_iter().position(|i| i.get_id() == id) {
-            Some(p) => Ok(self.as_vec_mut().get_mut(*p).unwrap()),
+        match self.position(id) {
+            Some(p) => Ok(&mut self.data[p]),
             None => Err(PackError::ObjectNotFound),
         }
     }
@@ -709,14 +1995,57 @@ where
     /// If ID is taken, returns false,
     /// otherwise returns true
     pub fn check_id_available(&self, id: &<T as VecPackMember>::Out) -> bool {
-        match self.iter().position(|i| i.get_id() == id) {
-            Some(_) => false,
-            None => true,
-        }
+        self.position(id).is_none()
+    }
+    /// Add a secondary index by a name and a key
+    /// extractor. It is built from the current members,
+    /// then updated on every insert, remove and save.
+    /// A Unique index returns PackError::UniqueIndexViolation
+    /// if two members would have the same key. An index
+    /// with the same name is replaced.
+    pub fn add_index<F>(
+        &mut self,
+        name: &str,
+        kind: index::IndexKind,
+        key: F,
+    ) -> PackResult<()>
+    where
+        F: Fn(&T) -> String + Send + Sync + 'static,
+    {
+        self.index.add_index(
+            name,
+            kind,
+            Arc::new(key),
+            self.data.iter().map(|pack| &pack.data),
+        )
+    }
+    /// Remove a secondary index by its name
+    /// Returns false if there is no such index
+    pub fn remove_index(&mut self, name: &str) -> bool {
+        self.index.remove_index(name)
+    }
+    /// Find members by a secondary index key
+    /// Returns PackError::ObjectNotFound if there
+    /// is no index with the given name.
+    pub fn find_by(&self, index: &str, key: &str) -> PackResult<Vec<&Pack<T>>> {
+        self.refresh_index();
+        Ok(self
+            .index
+            .find_by(index, key)?
+            .into_iter()
+            .filter_map(|position| self.data.get(position))
+            .collect())
     }
     /// Returns data as a mutable
     /// reference to Vec<Pack<T>>
+    /// The ID index is built again at the next lookup.
+    /// Members pushed through it are not attached to the
+    /// VecPack<T>; prefer insert_pack().
     pub fn as_vec_mut(&mut self) -> &mut Vec<Pack<T>> {
+        // Member saves check their changes by the
+        // indexes, so they must be current here
+        self.refresh_index();
+        self.index.set_stale();
         &mut self.data
     }
     /// Returns data as unmutable
@@ -729,6 +2058,232 @@ where
     pub fn get_path(&self) -> &Path {
         &self.path.as_path()
     }
+    /// Save all the members
+    /// of VecPack<T> to FS
+    pub fn save_all(&self) -> PackResult<()> {
+        for pack in &self.data {
+            pack.save()?;
+        }
+        Ok(())
+    }
+    /// Set compare on save
+    /// for every member. See Pack::set_compare_on_save()
+    pub fn set_compare_on_save(&mut self, compare_on_save: bool) {
+        self.options.compare_on_save = compare_on_save;
+        self.apply_options();
+    }
+    /// Set drop error handler
+    /// for every member. See Pack::set_drop_error_handler()
+    pub fn set_drop_error_handler(&mut self, handler: DropErrorHandler) {
+        self.options.drop_error_handler = handler;
+        self.apply_options();
+    }
+    /// Enable write-behind
+    /// for every member. See Pack::enable_write_behind()
+    pub fn enable_write_behind(
+        &mut self,
+        handle: &write_behind::WriteBehindHandle,
+        delay: Duration,
+        max_delay: Duration,
+    ) {
+        self.options.write_behind = Some(WriteBehindTarget {
+            handle: handle.clone(),
+            delay,
+            max_delay,
+        });
+        self.apply_options();
+    }
+    /// Flush pending background saves,
+    /// then save synchronously again
+    pub fn disable_write_behind(&mut self) -> PackResult<()> {
+        self.flush()?;
+        self.options.write_behind = None;
+        self.apply_options();
+        Ok(())
+    }
+    /// Barrier: wait until every pending
+    /// background save of the members is written
+    pub fn flush(&self) -> PackResult<()> {
+        match &self.options.write_behind {
+            Some(target) => target.handle.flush_all(),
+            None => Ok(()),
+        }
+    }
+    /// True if VecPack<T> is opened in read-only mode
+    pub fn is_read_only(&self) -> bool {
+        self.options.read_only
+    }
+    /// Set how many member packfiles can be kept open
+    /// When more are open, the ones opened first are
+    /// closed. Default is pool::DEFAULT_MAX_OPEN_FILES.
+    pub fn set_max_open_files(&mut self, max_open_files: usize) {
+        for pack in self.data.iter() {
+            pack.file.close();
+        }
+        self.options.file_pool = Some(pool::FilePool::new(max_open_files));
+        self.apply_options();
+    }
+    /// Max number of open member packfiles
+    pub fn max_open_files(&self) -> usize {
+        self.options
+            .file_pool
+            .as_ref()
+            .map(|pool| pool.capacity())
+            .unwrap_or(0)
+    }
+    /// Number of open member packfiles
+    pub fn open_files(&self) -> usize {
+        self.options
+            .file_pool
+            .as_ref()
+            .map(|pool| pool.open_files())
+            .unwrap_or(0)
+    }
+    /// Directory layout of the member files
+    pub fn layout(&self) -> layout::Layout {
+        self.layout
+    }
+    /// Migrate the member files to a new layout
+    /// A sharded layout is recorded in the manifest before the
+    /// members are moved, the flat one after it, so the members
+    /// are found even if the migration is interrupted. Call
+    /// set_layout() again to finish it.
+    pub fn set_layout(&mut self, layout: layout::Layout) -> PackResult<()> {
+        if self.options.read_only {
+            return Err(PackError::ReadOnly);
+        }
+        let sharded = layout != layout::Layout::Flat;
+        if sharded {
+            layout::write_manifest(&self.path, layout, &self.id_strategy)?;
+        }
+        self.layout = layout;
+        for pack in self.data.iter_mut() {
+            pack.member_layout = Some((self.path.clone(), layout));
+        }
+        self.relocate()?;
+        if !sharded {
+            layout::write_manifest(&self.path, layout, &self.id_strategy)?;
+        }
+        Ok(())
+    }
+    // Move members that are not at their layout path
+    // then remove the shard directories left empty
+    fn relocate(&mut self) -> PackResult<()> {
+        let mut moved = false;
+        for pack in self.data.iter_mut() {
+            let path = self.layout.member_path(&self.path, pack.get_id());
+            if pack.path != path {
+                pack.move_to(path)?;
+                moved = true;
+            }
+        }
+        if moved {
+            layout::remove_empty_dirs(&self.path)?;
+        }
+        Ok(())
+    }
+    // Apply VecPack options to every member
+    fn apply_options(&mut self) {
+        for pack in self.data.iter_mut() {
+            pack.options = self.options.clone();
+        }
+    }
+    // Attach a new member to the listeners
+    // and to the ID index of VecPack<T>
+    fn adopt(&self, pack: &mut Pack<T>) {
+        pack.parent_observers = Some(self.observers.clone());
+        pack.member_index = Some(self.index.clone());
+        pack.member_id = Some(member_id::<T>);
+        pack.member_layout = Some((self.path.clone(), self.layout));
+    }
+    // Push a new member and index it
+    fn push(&mut self, pack: Pack<T>) {
+        self.index.insert(&pack.data, self.data.len());
+        self.data.push(pack);
+    }
+    // Remove member at position from
+    // VecPack<T> and from the ID index
+    fn take(&mut self, position: usize) -> Pack<T> {
+        let mut pack = self.data.remove(position);
+        self.index.remove(&pack.data, position);
+        pack.member_index = None;
+        pack.member_layout = None;
+        pack
+    }
+    // Position of a member by its ID
+    // The index is built again if it is stale,
+    // or it does not match the members
+    fn position(&self, id: &<T as VecPackMember>::Out) -> Option<usize> {
+        let key = id.to_string();
+        let matches = |position: usize| {
+            self.data
+                .get(position)
+                .is_some_and(|pack| pack.get_id() == id)
+        };
+        if !self.index.is_stale() {
+            match self.index.get(&key) {
+                Some(position) if matches(position) => return Some(position),
+                None => return None,
+                Some(_) => (),
+            }
+        }
+        self.index.rebuild(self.data.iter().map(|pack| &pack.data));
+        self.index.get(&key).filter(|position| matches(*position))
+    }
+    // Check a loaded member file
+    // Returns PackError::IDMismatch if its filename is not
+    // the encoded ID inside the file
+    fn check_member(&self, pack: Pack<T>) -> PackResult<Pack<T>> {
+        let name = pack
+            .path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let id = pack.get_id().to_string();
+        match id::decode_filename(name) {
+            Some(name_id) if name_id == id => Ok(pack),
+            _ => Err(PackError::IDMismatch(name.to_string(), id)),
+        }
+    }
+    // Check the dotfiles of the VecPack directory
+    // Before IDs were encoded, members with an ID starting
+    // with '.' were dotfiles. A dotfile holding its own name
+    // as ID fails the check like any other misnamed member,
+    // other dotfiles are not members.
+    fn check_dot_files(
+        &mut self,
+        lenient: bool,
+        failures: &mut Vec<load::LoadFailure>,
+    ) -> PackResult<()> {
+        for file in layout::dot_files(&self.path)? {
+            if let Some(pack) = load_dot_member::<T>(&file) {
+                self.insert_loaded(&file, Ok(pack), lenient, failures)?;
+            }
+        }
+        Ok(())
+    }
+    // Build the indexes again if they are stale
+    fn refresh_index(&self) {
+        self.index.refresh(self.data.iter().map(|pack| &pack.data));
+    }
+}
+
+impl<T> RepositoryMember for VecPack<T>
+where
+    for<'de> T: VecPackMember + Deserialize<'de>,
+{
+    // Bad member files are returned as errors,
+    // so the repository can collect them
+    fn load_member(root: &Path, path: &str) -> PackResult<Self> {
+        VecPack::load_or_init(root.join(path.trim_start_matches('/')))
+    }
+    fn save_member(&self) -> PackResult<()> {
+        self.save_all()
+    }
+    fn flush_member(&self) -> PackResult<()> {
+        self.save_all()?;
+        self.flush()
+    }
 }
 
 // Deref implementation for VecPack<T>
@@ -808,10 +2363,11 @@ where
 
 impl<'a, T> PackGuard<'a, T>
 where
-    for<'de> T: Serialize + Deserialize<'de> + Default + Sized + Clone + 'a,
+    T: Serialize + Sized + Clone + 'a,
 {
     pub fn unpack(&mut self) -> &mut T {
-        &mut self.data
+        self.touch();
+        &mut self.pack.data
     }
 }
 
@@ -0,0 +1,271 @@
+//! Parallel loading
+//!
+//! VecPack::load_parallel() reads and deserializes the member files
+//! on a pool of worker threads. Files are loaded in batches, so only
+//! one batch of loaded members waits to be inserted at a time, and
+//! they are inserted in filename order, so the result does not depend
+//! on the thread timing. Every file is timed in the LoadReport.
+//! In lenient mode, files that cannot be loaded are listed in the
+//! LoadReport instead of failing the whole load. Bad files are moved
+//! into the quarantine directory, but valid members whose ID conflicts
+//! with an other member stay in place.
+//! VecPack::load_or_init_lenient() and try_load_or_init_lenient()
+//! load the same way on the calling thread.
+
+use crate::*;
+use std::sync::atomic::AtomicUsize;
+use std::time::Instant;
+
+/// Options of VecPack::load_parallel()
+#[derive(Debug, Clone)]
+pub struct LoadOptions {
+    /// Number of worker threads
+    /// Default is the available parallelism
+    pub threads: usize,
+    /// Max number of loaded members waiting to be inserted
+    /// Default is 1024
+    pub batch_size: usize,
+    /// Report bad files instead of returning an error
+    /// Default is false
+    pub lenient: bool,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        LoadOptions {
+            threads: std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            batch_size: 1024,
+            lenient: false,
+        }
+    }
+}
+
+impl LoadOptions {
+    /// Set the number of worker threads
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+    /// Set the max number of loaded
+    /// members waiting to be inserted
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+    /// Set lenient mode
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+}
+
+/// Load time of a single member file
+#[derive(Debug, Clone)]
+pub struct FileTiming {
+    pub path: PathBuf,
+    /// File size in bytes
+    pub bytes: u64,
+    /// Read and deserialize time
+    pub duration: Duration,
+}
+
+/// Member file that could not be loaded
+#[derive(Debug)]
+pub struct LoadFailure {
+    /// Original path of the file
+    pub path: PathBuf,
+    /// Path in the quarantine directory
+    /// None if the file is a valid member with a
+    /// conflicting ID, and it is left in place
+    pub quarantined: Option<PathBuf>,
+    pub error: PackError,
+}
+
+/// Report of VecPack::load_parallel()
+#[derive(Debug, Default)]
+pub struct LoadReport {
+    /// Loaded files in insert order
+    pub files: Vec<FileTiming>,
+    /// Files not loaded in lenient mode
+    pub failures: Vec<LoadFailure>,
+    /// Number of worker threads used
+    pub threads: usize,
+    /// Wall time of the whole load
+    pub total: Duration,
+}
+
+impl LoadReport {
+    /// The n slowest files
+    pub fn slowest(&self, n: usize) -> Vec<&FileTiming> {
+        let mut files = self.files.iter().collect::<Vec<&FileTiming>>();
+        files.sort_by_key(|file| std::cmp::Reverse(file.duration));
+        files.truncate(n);
+        files
+    }
+}
+
+impl<T> VecPack<T>
+where
+    for<'de> T: VecPackMember + Deserialize<'de> + Send,
+{
+    /// Load or init VecPack<T> on worker threads
+    /// Same as VecPack::load_or_init(), but the member files
+    /// are loaded in parallel, and the load is reported per
+    /// file. Members are inserted in filename order. Returns
+    /// the first PackError::MemberLoadError, or in lenient
+    /// mode lists the files in LoadReport::failures.
+    /// See VecPack::load_or_init_lenient().
+    ///
+    /// ```rust,no_run
+    /// # use packman::*;
+    /// # use packman::load::LoadOptions;
+    /// # use serde::{Deserialize, Serialize};
+    /// # use std::path::PathBuf;
+    /// # #[derive(Serialize, Deserialize, Clone)]
+    /// # struct Customer { id: u32 }
+    /// # impl VecPackMember for Customer {
+    /// #     type Out = u32;
+    /// #     fn get_id(&self) -> &u32 { &self.id }
+    /// # }
+    /// let (customers, report) = VecPack::<Customer>::load_parallel(
+    ///     PathBuf::from("data/customers"),
+    ///     LoadOptions::default().threads(8),
+    /// )?;
+    /// for file in report.slowest(5) {
+    ///     println!("{}: {:?}", file.path.display(), file.duration);
+    /// }
+    /// # Ok::<(), PackError>(())
+    /// ```
+    pub fn load_parallel(
+        path: PathBuf,
+        options: LoadOptions,
+    ) -> PackResult<(VecPack<T>, LoadReport)> {
+        let start = Instant::now();
+        let mut result: VecPack<T> = VecPack::new(path.clone())?;
+        let mut files = layout::member_files(&path, result.layout)?;
+        files.sort();
+        let threads = options.threads.max(1).min(files.len().max(1));
+        let mut report = LoadReport {
+            files: Vec::with_capacity(files.len()),
+            failures: Vec::new(),
+            threads,
+            total: Duration::default(),
+        };
+        for batch in files.chunks(options.batch_size.max(1)) {
+            let loaded = load_batch::<T>(batch, threads);
+            for (file, loaded) in batch.iter().zip(loaded) {
+                let (loaded, timing) = match loaded {
+                    Ok((pack, timing)) => (Ok(pack), Some(timing)),
+                    Err(error) => (Err(error), None),
+                };
+                if result.insert_loaded(
+                    file,
+                    loaded,
+                    options.lenient,
+                    &mut report.failures,
+                )? {
+                    report.files.extend(timing);
+                }
+            }
+        }
+        result.check_dot_files(options.lenient, &mut report.failures)?;
+        report.total = start.elapsed();
+        Ok((result, report))
+    }
+}
+
+impl<T> VecPack<T>
+where
+    for<'de> T: VecPackMember + Deserialize<'de>,
+{
+    // Check and insert a loaded member file
+    // Returns false if it is not inserted. In lenient mode
+    // the file is listed in failures instead of returning
+    // an error, and it is quarantined unless it is a valid
+    // member with a conflicting ID.
+    pub(crate) fn insert_loaded(
+        &mut self,
+        file: &Path,
+        loaded: PackResult<Pack<T>>,
+        lenient: bool,
+        failures: &mut Vec<LoadFailure>,
+    ) -> PackResult<bool> {
+        let error = match loaded
+            .and_then(|pack| self.check_member(pack))
+            .and_then(|pack| self.insert_pack(pack))
+        {
+            Ok(()) => return Ok(true),
+            Err(error) if lenient => error,
+            Err(error) => return Err(member_load_error(file, error)),
+        };
+        let quarantined = match error {
+            PackError::IDTaken
+            | PackError::IDCollision(_, _)
+            | PackError::IDMismatch(_, _)
+            | PackError::UniqueIndexViolation(_, _) => None,
+            _ => Some(layout::quarantine(&self.path, file)?),
+        };
+        failures.push(LoadFailure {
+            path: file.to_path_buf(),
+            quarantined,
+            error,
+        });
+        Ok(false)
+    }
+}
+
+type Loaded<T> = PackResult<(Pack<T>, FileTiming)>;
+
+// Load a batch of member files on worker threads
+// Results are in the order of the batch
+fn load_batch<T>(batch: &[PathBuf], threads: usize) -> Vec<Loaded<T>>
+where
+    for<'de> T: VecPackMember + Deserialize<'de> + Send,
+{
+    let next = AtomicUsize::new(0);
+    let mut results = std::thread::scope(|scope| {
+        let workers = (0..threads.min(batch.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut loaded = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        match batch.get(i) {
+                            Some(path) => loaded.push((i, load_file(path))),
+                            None => return loaded,
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .flat_map(|worker| match worker.join() {
+                Ok(loaded) => loaded,
+                Err(panic) => std::panic::resume_unwind(panic),
+            })
+            .collect::<Vec<(usize, Loaded<T>)>>()
+    });
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, loaded)| loaded).collect()
+}
+
+// Load a single member file
+fn load_file<T>(path: &Path) -> Loaded<T>
+where
+    for<'de> T: VecPackMember + Deserialize<'de>,
+{
+    let start = Instant::now();
+    let bytes = std::fs::metadata(path)?.len();
+    let pack = Pack::load_from_path(path.to_path_buf())?;
+    Ok((
+        pack,
+        FileTiming {
+            path: path.to_path_buf(),
+            bytes,
+            duration: start.elapsed(),
+        },
+    ))
+}
@@ -0,0 +1,289 @@
+//! Change subscriptions
+//!
+//! Listeners subscribed to a Pack<T> or a VecPack<T> get a ChangeEvent
+//! after every successful save, insert or remove. A listener is either
+//! a callback or an mpsc channel, and it is unsubscribed when its
+//! Subscription handle is dropped.
+
+use crate::*;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Weak;
+
+/// Kind of a change
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChangeKind {
+    /// Pack<T> or VecPack<T> member is saved
+    Saved,
+    /// Save of Pack<T> or a VecPack<T> member is handed
+    /// over to its write-behind worker. The event has the
+    /// version of the last write, and no Saved event
+    /// follows when the worker writes the packfile.
+    Queued,
+    /// New member is inserted into VecPack<T>
+    Inserted,
+    /// Member is removed from VecPack<T>
+    Removed,
+}
+
+/// ChangeEvent<T>
+/// Delivered to the listeners after a change
+#[derive(Debug, Clone)]
+pub struct ChangeEvent<T> {
+    pub kind: ChangeKind,
+    /// File name of the changed pack, for VecPack<T>
+    /// members the member ID after the change
+    pub id: String,
+    /// Packfile version after the change
+    pub version: u64,
+    /// Value before the change, only for listeners
+    /// subscribed with values, and only when it is known
+    pub old: Option<T>,
+    /// Value after the change, only for
+    /// listeners subscribed with values
+    pub new: Option<T>,
+}
+
+/// Subscription
+/// Listener handle, the listener is
+/// unsubscribed when it is dropped
+#[must_use = "the listener is unsubscribed when the Subscription is dropped"]
+pub struct Subscription {
+    unsubscribe: Option<Box<dyn FnOnce() + Send + Sync>>,
+}
+
+impl Subscription {
+    /// Unsubscribe the listener
+    pub fn unsubscribe(self) {}
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Some(unsubscribe) = self.unsubscribe.take() {
+            unsubscribe();
+        }
+    }
+}
+
+type Callback<T> = Arc<dyn Fn(&ChangeEvent<T>) + Send + Sync>;
+
+struct Listener<T> {
+    id: u64,
+    with_values: bool,
+    callback: Callback<T>,
+}
+
+struct ListenerList<T> {
+    next_id: u64,
+    listeners: Vec<Listener<T>>,
+}
+
+// Listeners of a Pack<T> or a VecPack<T>
+// VecPack<T> members share the listeners of their VecPack<T>
+pub(crate) struct Observers<T> {
+    inner: Arc<Mutex<ListenerList<T>>>,
+}
+
+impl<T> Clone for Observers<T> {
+    fn clone(&self) -> Self {
+        Observers {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Default for Observers<T> {
+    fn default() -> Self {
+        Observers {
+            inner: Arc::new(Mutex::new(ListenerList {
+                next_id: 0,
+                listeners: Vec::new(),
+            })),
+        }
+    }
+}
+
+impl<T> Observers<T>
+where
+    T: Clone + Send + 'static,
+{
+    pub(crate) fn subscribe<F>(
+        &self,
+        with_values: bool,
+        callback: F,
+    ) -> Subscription
+    where
+        F: Fn(&ChangeEvent<T>) + Send + Sync + 'static,
+    {
+        let mut list = self.list();
+        let id = list.next_id;
+        list.next_id += 1;
+        list.listeners.push(Listener {
+            id,
+            with_values,
+            callback: Arc::new(callback),
+        });
+        let weak: Weak<Mutex<ListenerList<T>>> = Arc::downgrade(&self.inner);
+        Subscription {
+            unsubscribe: Some(Box::new(move || {
+                if let Some(inner) = weak.upgrade() {
+                    inner
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .listeners
+                        .retain(|l| l.id != id);
+                }
+            })),
+        }
+    }
+    pub(crate) fn subscribe_channel(
+        &self,
+        with_values: bool,
+    ) -> (Subscription, Receiver<ChangeEvent<T>>) {
+        let (sender, receiver) = channel();
+        let subscription = self.subscribe(with_values, move |event| {
+            // Receiver could be dropped
+            let _ = sender.send(event.clone());
+        });
+        (subscription, receiver)
+    }
+}
+
+impl<T> Observers<T>
+where
+    T: Clone,
+{
+    // Call every listener
+    // Values are only cloned if a listener needs them
+    pub(crate) fn notify(
+        &self,
+        kind: ChangeKind,
+        id: &str,
+        version: u64,
+        old: Option<&T>,
+        new: Option<&T>,
+    ) {
+        // Listeners are called without the lock,
+        // so they can subscribe or unsubscribe
+        let listeners: Vec<(bool, Callback<T>)> = self
+            .list()
+            .listeners
+            .iter()
+            .map(|l| (l.with_values, l.callback.clone()))
+            .collect();
+        if listeners.is_empty() {
+            return;
+        }
+        let mut event = ChangeEvent {
+            kind,
+            id: id.to_string(),
+            version,
+            old: None,
+            new: None,
+        };
+        for (_, callback) in listeners.iter().filter(|(values, _)| !values) {
+            callback(&event);
+        }
+        if listeners.iter().any(|(values, _)| *values) {
+            event.old = old.cloned();
+            event.new = new.cloned();
+            for (_, callback) in listeners.iter().filter(|(values, _)| *values)
+            {
+                callback(&event);
+            }
+        }
+    }
+    pub(crate) fn is_empty(&self) -> bool {
+        self.list().listeners.is_empty()
+    }
+    fn list(&self) -> std::sync::MutexGuard<'_, ListenerList<T>> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Listeners of one change
+// It is taken while the pack is locked, and sent after
+// the lock is released, so listeners can use the pack.
+pub(crate) struct Notice<T> {
+    kind: ChangeKind,
+    id: String,
+    version: u64,
+    observers: Vec<Observers<T>>,
+}
+
+impl<T> Notice<T>
+where
+    T: Clone,
+{
+    pub(crate) fn new(
+        kind: ChangeKind,
+        id: String,
+        version: u64,
+        observers: Vec<Observers<T>>,
+    ) -> Self {
+        Notice {
+            kind,
+            id,
+            version,
+            observers,
+        }
+    }
+    // True if there is no listener to notify
+    pub(crate) fn is_empty(&self) -> bool {
+        self.observers.iter().all(|o| o.is_empty())
+    }
+    pub(crate) fn send(&self, old: Option<&T>, new: Option<&T>) {
+        for observers in self.observers.iter() {
+            observers.notify(self.kind, &self.id, self.version, old, new);
+        }
+    }
+}
+
+impl<T> Pack<T>
+where
+    T: Serialize + Sized + Clone + Send + 'static,
+{
+    /// Subscribe a callback to the changes of Pack<T>
+    /// If with_values is true, then events carry
+    /// the old and the new T.
+    pub fn subscribe<F>(&self, with_values: bool, callback: F) -> Subscription
+    where
+        F: Fn(&ChangeEvent<T>) + Send + Sync + 'static,
+    {
+        self.observers.subscribe(with_values, callback)
+    }
+    /// Subscribe a channel to the changes of Pack<T>
+    /// If with_values is true, then events carry
+    /// the old and the new T.
+    pub fn subscribe_channel(
+        &self,
+        with_values: bool,
+    ) -> (Subscription, Receiver<ChangeEvent<T>>) {
+        self.observers.subscribe_channel(with_values)
+    }
+}
+
+impl<T> VecPack<T>
+where
+    T: VecPackMember + Send + 'static,
+{
+    /// Subscribe a callback to every insert, remove
+    /// and member save of VecPack<T>
+    /// If with_values is true, then events carry
+    /// the old and the new T.
+    pub fn subscribe<F>(&self, with_values: bool, callback: F) -> Subscription
+    where
+        F: Fn(&ChangeEvent<T>) + Send + Sync + 'static,
+    {
+        self.observers.subscribe(with_values, callback)
+    }
+    /// Subscribe a channel to every insert, remove
+    /// and member save of VecPack<T>
+    /// If with_values is true, then events carry
+    /// the old and the new T.
+    pub fn subscribe_channel(
+        &self,
+        with_values: bool,
+    ) -> (Subscription, Receiver<ChangeEvent<T>>) {
+        self.observers.subscribe_channel(with_values)
+    }
+}
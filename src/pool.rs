@@ -0,0 +1,147 @@
+//! Open packfile handles
+//!
+//! Pack<T> keeps its packfile open between saves, so the superblock
+//! and the inodes are not read again on every write. Members of a
+//! VecPack<T> share a FilePool, that closes the packfiles opened
+//! first when too many of them are open.
+
+use crate::*;
+use std::collections::VecDeque;
+use std::sync::{MutexGuard, Weak};
+
+/// Default number of open packfiles
+/// of a VecPack<T>
+pub const DEFAULT_MAX_OPEN_FILES: usize = 64;
+
+type Handle = Mutex<Option<fs::PackFile>>;
+
+// Open packfile of a Pack<T>
+// It is opened on the first write, and closed when the pool
+// evicts it, or when the packfile is replaced by anything else.
+// If it is written by anything else, its inodes are read again.
+#[derive(Default)]
+pub(crate) struct FileSlot {
+    handle: Arc<Handle>,
+}
+
+impl FileSlot {
+    // Call f with the open packfile
+    // Opens (or inits) the packfile if it is not open, or
+    // the path refers to an other file. After an error
+    // the packfile is closed, so the next call opens it again.
+    pub(crate) fn with<F, R>(
+        &self,
+        path: &Path,
+        workspace_id: Option<u64>,
+        pool: Option<&FilePool>,
+        f: F,
+    ) -> PackResult<R>
+    where
+        F: FnOnce(&mut fs::PackFile) -> PackResult<R>,
+    {
+        let mut opened = false;
+        let res = {
+            let mut handle = self.lock();
+            // The packfile could be removed, replaced
+            // or written since it has been opened
+            let current = match handle.take() {
+                Some(mut pack_file) => {
+                    pack_file.check_current()?.then_some(pack_file)
+                }
+                None => None,
+            };
+            let mut pack_file = match current {
+                Some(pack_file) => pack_file,
+                None => {
+                    opened = true;
+                    fs::PackFile::open_or_init(
+                        path,
+                        0,
+                        None,
+                        None,
+                        workspace_id,
+                    )?
+                }
+            };
+            let res = f(&mut pack_file);
+            if res.is_ok() {
+                *handle = Some(pack_file);
+            }
+            res
+        };
+        // Registered without holding the handle lock,
+        // as the pool locks the handles it closes
+        if let (true, Ok(_), Some(pool)) = (opened, &res, pool) {
+            pool.register(&self.handle);
+        }
+        res
+    }
+    // Close the packfile
+    // Next write opens it again
+    pub(crate) fn close(&self) {
+        *self.lock() = None;
+    }
+    fn lock(&self) -> MutexGuard<'_, Option<fs::PackFile>> {
+        self.handle.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+struct PoolInner {
+    capacity: usize,
+    // Open handles, the first opened is the first
+    open: VecDeque<Weak<Handle>>,
+}
+
+// Bounded set of open packfiles
+// shared by the members of a VecPack<T>
+#[derive(Clone)]
+pub(crate) struct FilePool {
+    inner: Arc<Mutex<PoolInner>>,
+}
+
+impl FilePool {
+    // Capacity is at least one
+    pub(crate) fn new(capacity: usize) -> Self {
+        FilePool {
+            inner: Arc::new(Mutex::new(PoolInner {
+                capacity: capacity.max(1),
+                open: VecDeque::new(),
+            })),
+        }
+    }
+    pub(crate) fn capacity(&self) -> usize {
+        self.lock().capacity
+    }
+    // Number of open packfiles
+    pub(crate) fn open_files(&self) -> usize {
+        self.lock()
+            .open
+            .iter()
+            .filter_map(|handle| handle.upgrade())
+            .filter(|handle| {
+                handle.lock().unwrap_or_else(|e| e.into_inner()).is_some()
+            })
+            .count()
+    }
+    // Add a newly opened handle
+    // If the pool is full, then the
+    // first opened ones are closed
+    fn register(&self, handle: &Arc<Handle>) {
+        let mut inner = self.lock();
+        let new = Arc::downgrade(handle);
+        inner
+            .open
+            .retain(|h| h.strong_count() > 0 && !h.ptr_eq(&new));
+        inner.open.push_back(new);
+        while inner.open.len() > inner.capacity {
+            if let Some(handle) =
+                inner.open.pop_front().and_then(|h| h.upgrade())
+            {
+                *handle.lock().unwrap_or_else(|e| e.into_inner()) = None;
+            }
+        }
+    }
+    fn lock(&self) -> MutexGuard<'_, PoolInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,384 @@
+//! Queries
+//!
+//! Query builder over the members of a VecPack<T>: predicates,
+//! multi-key sort, offset and limit, and cursor based pagination.
+//! Key conditions use the secondary indexes of VecPack<T> when they
+//! exist. Results are borrowed from VecPack<T>.
+
+use crate::*;
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+type Filter<'a, T> = Box<dyn Fn(&T) -> bool + 'a>;
+type Compare<'a, T> = Box<dyn Fn(&T, &T) -> Ordering + 'a>;
+type Encode<'a, T> = Box<dyn Fn(&T) -> Value + 'a>;
+type CompareValue<'a, T> = Box<dyn Fn(&T, &Value) -> PackResult<Ordering> + 'a>;
+
+// Sort key of a query
+// Key sorts also compare a member with the key value
+// in a cursor, so the member of the cursor is not needed
+struct Sort<'a, T> {
+    compare: Compare<'a, T>,
+    key: Option<(Encode<'a, T>, CompareValue<'a, T>)>,
+}
+
+// Key equals value condition
+struct KeyCondition<'a, T> {
+    index: String,
+    value: String,
+    key: Box<dyn Fn(&T) -> String + 'a>,
+}
+
+/// Cursor
+/// Position of a page in the query results. It holds
+/// the ID and the sort key values of the last member of
+/// the previous page as JSON, so it can be sent to a client
+/// as a string, and it stays valid when that member is
+/// removed. The default cursor is before the first result.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Cursor(String);
+
+impl Cursor {
+    /// Cursor as a string
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+// Content of a cursor
+// Keys of sorts by compare functions are null
+#[derive(Serialize, Deserialize)]
+struct CursorData {
+    id: String,
+    keys: Vec<Value>,
+}
+
+impl From<String> for Cursor {
+    fn from(cursor: String) -> Self {
+        Cursor(cursor)
+    }
+}
+
+impl From<&str> for Cursor {
+    fn from(cursor: &str) -> Self {
+        Cursor(cursor.to_string())
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Page
+/// Members of a page, and the cursor
+/// of the next page if there is any
+pub struct Page<'a, T>
+where
+    T: VecPackMember,
+{
+    pub items: Vec<&'a Pack<T>>,
+    pub next: Option<Cursor>,
+}
+
+/// Query<T>
+/// Built by VecPack::query()
+///
+/// ```rust,no_run
+/// # use packman::*;
+/// # use serde::{Deserialize, Serialize};
+/// # use std::path::PathBuf;
+/// # #[derive(Serialize, Deserialize, Clone)]
+/// # struct Customer { id: u32, name: String, status: String }
+/// # impl VecPackMember for Customer {
+/// #     type Out = u32;
+/// #     fn get_id(&self) -> &u32 { &self.id }
+/// # }
+/// let customers: VecPack<Customer> =
+///     VecPack::load_or_init(PathBuf::from("data/customers"))?;
+/// let page = customers
+///     .query()
+///     .index_eq("status", "active", |c| c.status.clone())
+///     .sort_by_key(|c| c.name.clone())
+///     .page(20)?;
+/// // Next page
+/// if let Some(cursor) = page.next {
+///     let page = customers
+///         .query()
+///         .index_eq("status", "active", |c| c.status.clone())
+///         .sort_by_key(|c| c.name.clone())
+///         .after(cursor)
+///         .page(20)?;
+/// }
+/// # Ok::<(), PackError>(())
+/// ```
+pub struct Query<'a, T>
+where
+    T: VecPackMember,
+{
+    pack: &'a VecPack<T>,
+    conditions: Vec<KeyCondition<'a, T>>,
+    filters: Vec<Filter<'a, T>>,
+    sort: Vec<Sort<'a, T>>,
+    offset: usize,
+    limit: Option<usize>,
+    after: Option<Cursor>,
+}
+
+impl<'a, T> Query<'a, T>
+where
+    T: VecPackMember,
+{
+    pub(crate) fn new(pack: &'a VecPack<T>) -> Self {
+        Query {
+            pack,
+            conditions: Vec::new(),
+            filters: Vec::new(),
+            sort: Vec::new(),
+            offset: 0,
+            limit: None,
+            after: None,
+        }
+    }
+    /// Keep members matching the predicate
+    pub fn filter<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&T) -> bool + 'a,
+    {
+        self.filters.push(Box::new(predicate));
+        self
+    }
+    /// Keep members whose key equals value
+    /// If VecPack<T> has a secondary index with the given
+    /// name, then it is used; otherwise the key extractor
+    /// is called for every member.
+    pub fn index_eq<F>(mut self, index: &str, value: &str, key: F) -> Self
+    where
+        F: Fn(&T) -> String + 'a,
+    {
+        self.conditions.push(KeyCondition {
+            index: index.to_string(),
+            value: value.to_string(),
+            key: Box::new(key),
+        });
+        self
+    }
+    /// Sort by a compare function
+    /// Every call adds a sort key; members equal by every
+    /// key are sorted by their ID. A cursor of this query
+    /// needs its member, as the compare function has no
+    /// key value to keep in the cursor.
+    pub fn sort_by<F>(mut self, compare: F) -> Self
+    where
+        F: Fn(&T, &T) -> Ordering + 'a,
+    {
+        self.sort.push(Sort {
+            compare: Box::new(compare),
+            key: None,
+        });
+        self
+    }
+    /// Sort by a key in ascending order
+    pub fn sort_by_key<F, K>(self, key: F) -> Self
+    where
+        F: Fn(&T) -> K + 'a,
+        for<'de> K: Ord + Serialize + Deserialize<'de>,
+    {
+        self.sort_by_key_order(key, false)
+    }
+    /// Sort by a key in descending order
+    pub fn sort_by_key_desc<F, K>(self, key: F) -> Self
+    where
+        F: Fn(&T) -> K + 'a,
+        for<'de> K: Ord + Serialize + Deserialize<'de>,
+    {
+        self.sort_by_key_order(key, true)
+    }
+    fn sort_by_key_order<F, K>(mut self, key: F, desc: bool) -> Self
+    where
+        F: Fn(&T) -> K + 'a,
+        for<'de> K: Ord + Serialize + Deserialize<'de>,
+    {
+        let order = move |ord: Ordering| if desc { ord.reverse() } else { ord };
+        let key = Rc::new(key);
+        let (encode_key, value_key) = (key.clone(), key.clone());
+        self.sort.push(Sort {
+            compare: Box::new(move |a, b| order(key(a).cmp(&key(b)))),
+            key: Some((
+                Box::new(move |item| {
+                    serde_json::to_value(encode_key(item)).unwrap_or_default()
+                }),
+                Box::new(move |item, value| {
+                    Ok(order(value_key(item).cmp(&K::deserialize(value)?)))
+                }),
+            )),
+        });
+        self
+    }
+    /// Skip the first n results
+    /// Ignored after a cursor, as the cursor of a page
+    /// already points past the skipped results.
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+    /// Return at most n results
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+    /// Only return results after the cursor
+    /// Use the same conditions and sort as the
+    /// query which returned the cursor.
+    pub fn after(mut self, cursor: Cursor) -> Self {
+        self.after = Some(cursor);
+        self
+    }
+    /// Run the query
+    /// Returns PackError::ObjectNotFound if the query sorts
+    /// by a compare function, and the member of the cursor
+    /// has been removed.
+    pub fn run(self) -> PackResult<Vec<&'a Pack<T>>> {
+        let pack = self.pack;
+        Ok(self
+            .positions()?
+            .into_iter()
+            .map(|position| &pack.data[position])
+            .collect())
+    }
+    /// Run the query, and return the first page
+    /// of the given size with the cursor of the next one
+    pub fn page(mut self, size: usize) -> PackResult<Page<'a, T>> {
+        let pack = self.pack;
+        let size = self.limit.map_or(size, |limit| limit.min(size));
+        // One more to know whether there is a next page
+        self.limit = Some(size + 1);
+        let mut positions = self.positions()?;
+        let mut next = None;
+        if positions.len() > size {
+            positions.truncate(size);
+            // An empty page starts the next one where it started
+            next = Some(match positions.last() {
+                Some(p) => self.cursor(&pack.data[*p].data)?,
+                None => self.after.clone().unwrap_or_default(),
+            });
+        }
+        Ok(Page {
+            items: positions
+                .into_iter()
+                .map(|position| &pack.data[position])
+                .collect(),
+            next,
+        })
+    }
+    /// Number of results
+    pub fn count(self) -> PackResult<usize> {
+        Ok(self.positions()?.len())
+    }
+    // Positions of the results in order
+    fn positions(&self) -> PackResult<Vec<usize>> {
+        let data = &self.pack.data;
+        let index = &self.pack.index;
+        index.refresh(data.iter().map(|pack| &pack.data));
+        // Candidates from the first indexed condition
+        let mut positions = self
+            .conditions
+            .iter()
+            .find_map(|c| index.find_by(&c.index, &c.value).ok())
+            .map(|mut positions| {
+                positions.sort_unstable();
+                positions
+            })
+            .unwrap_or_else(|| (0..data.len()).collect());
+        positions.retain(|position| {
+            let item = &data[*position].data;
+            self.conditions.iter().all(|c| (c.key)(item) == c.value)
+                && self.filters.iter().all(|f| f(item))
+        });
+        // Same order in every run, even if members
+        // are removed: equal members by their ID
+        let compare = |a: &usize, b: &usize| {
+            let (a, b) = (&data[*a].data, &data[*b].data);
+            self.sort
+                .iter()
+                .map(|sort| (sort.compare)(a, b))
+                .find(|ord| *ord != Ordering::Equal)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| index::id_key(a).cmp(&index::id_key(b)))
+        };
+        positions.sort_by(compare);
+        let mut offset = self.offset;
+        if let Some(cursor) = self.after.as_ref().filter(|c| !c.0.is_empty()) {
+            offset = 0;
+            let cursor: CursorData = serde_json::from_str(&cursor.0)?;
+            let mut after = Vec::with_capacity(positions.len());
+            for position in positions {
+                let ord = self.compare_cursor(&data[position].data, &cursor)?;
+                if ord == Ordering::Greater {
+                    after.push(position);
+                }
+            }
+            positions = after;
+        }
+        Ok(positions
+            .into_iter()
+            .skip(offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect())
+    }
+    // Cursor after a member
+    fn cursor(&self, item: &T) -> PackResult<Cursor> {
+        let data = CursorData {
+            id: index::id_key(item),
+            keys: self
+                .sort
+                .iter()
+                .map(|sort| match &sort.key {
+                    Some((encode, _)) => encode(item),
+                    None => Value::Null,
+                })
+                .collect(),
+        };
+        Ok(Cursor(serde_json::to_string(&data)?))
+    }
+    // Order of a member and the member of a cursor
+    // Key values are taken from the cursor, the
+    // member is only looked up for compare functions.
+    fn compare_cursor(
+        &self,
+        item: &T,
+        cursor: &CursorData,
+    ) -> PackResult<Ordering> {
+        for (i, sort) in self.sort.iter().enumerate() {
+            let ord = match (&sort.key, cursor.keys.get(i)) {
+                (Some((_, compare_value)), Some(value)) => {
+                    compare_value(item, value)?
+                }
+                _ => {
+                    let position = self
+                        .pack
+                        .index
+                        .get(&cursor.id)
+                        .ok_or(PackError::ObjectNotFound)?;
+                    (sort.compare)(item, &self.pack.data[position].data)
+                }
+            };
+            if ord != Ordering::Equal {
+                return Ok(ord);
+            }
+        }
+        Ok(index::id_key(item).cmp(&cursor.id))
+    }
+}
+
+impl<T> VecPack<T>
+where
+    T: VecPackMember,
+{
+    /// Query builder over the members
+    pub fn query(&self) -> Query<'_, T> {
+        Query::new(self)
+    }
+}
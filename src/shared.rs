@@ -0,0 +1,678 @@
+//! Thread-safe shared packs
+//!
+//! SharedPack<T> is a cloneable handle around a Pack<T>, with
+//! RwLock semantics. Readers never block each other, writers are
+//! serialized. A writer works on its own copy of T, and the copy is
+//! saved to FS before it replaces the shared data; so readers are
+//! not blocked while the file is written.
+//!
+//! A PackWatcher polls the packfile of a SharedPack<T>, and reloads
+//! it when an other process (or the packman tool) changes it.
+
+use crate::*;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{
+    MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError,
+};
+use std::thread::JoinHandle;
+
+/// SharedPack<T>
+/// Cloneable, thread-safe handle of a Pack<T>
+///
+/// ```rust,no_run
+/// use packman::shared::SharedPack;
+/// use packman::*;
+/// # use std::path::PathBuf;
+/// let counter: SharedPack<u32> =
+///     Pack::<u32>::load_or_init(PathBuf::from("data"), "counter")?
+///         .into_shared();
+/// let handle = counter.clone();
+/// std::thread::spawn(move || *handle.write() += 1).join().unwrap();
+/// assert_eq!(*counter.read(), 1);
+/// # Ok::<(), PackError>(())
+/// ```
+pub struct SharedPack<T>
+where
+    T: Serialize + Sized + Clone,
+{
+    inner: Arc<SharedInner<T>>,
+}
+
+struct SharedInner<T>
+where
+    T: Serialize + Sized + Clone,
+{
+    pack: RwLock<Pack<T>>,
+    // Serializes writers
+    write_lock: Mutex<()>,
+}
+
+/// SharedPackReadGuard<'a, T>
+/// Shared read access to T
+pub struct SharedPackReadGuard<'a, T>
+where
+    T: Serialize + Sized + Clone,
+{
+    guard: RwLockReadGuard<'a, Pack<T>>,
+}
+
+/// SharedPackWriteGuard<'a, T>
+/// Exclusive write access to a copy of T.
+/// When released, the copy is saved, and if save
+/// succeeds, it becomes the shared data.
+/// Use commit() to get the save result, or abort()
+/// to discard the changes.
+pub struct SharedPackWriteGuard<'a, T>
+where
+    T: Serialize + Sized + Clone,
+{
+    inner: &'a SharedInner<T>,
+    data: T,
+    dirty: bool,
+    finished: bool,
+    // Held until the copy is saved
+    write_lock: Option<MutexGuard<'a, ()>>,
+}
+
+impl<T> Clone for SharedPack<T>
+where
+    T: Serialize + Sized + Clone,
+{
+    fn clone(&self) -> Self {
+        SharedPack {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Pack<T>
+where
+    T: Serialize + Sized + Clone,
+{
+    /// Turn Pack<T> into a
+    /// thread-safe SharedPack<T>
+    pub fn into_shared(self) -> SharedPack<T> {
+        SharedPack::new(self)
+    }
+}
+
+impl<T> SharedPack<T>
+where
+    T: Serialize + Sized + Clone,
+{
+    /// New SharedPack<T> from Pack<T>
+    pub fn new(pack: Pack<T>) -> Self {
+        SharedPack {
+            inner: Arc::new(SharedInner {
+                pack: RwLock::new(pack),
+                write_lock: Mutex::new(()),
+            }),
+        }
+    }
+    /// Shared read access
+    /// Blocks only while a writer replaces the data
+    pub fn read(&self) -> SharedPackReadGuard<'_, T> {
+        SharedPackReadGuard {
+            guard: self.inner.read_pack(),
+        }
+    }
+    /// Exclusive write access
+    /// Blocks while an other writer is active
+    pub fn write(&self) -> SharedPackWriteGuard<'_, T> {
+        let write_lock = self
+            .inner
+            .write_lock
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let data = self.inner.read_pack().data.clone();
+        SharedPackWriteGuard {
+            inner: &self.inner,
+            data,
+            dirty: false,
+            finished: false,
+            write_lock: Some(write_lock),
+        }
+    }
+    /// Update T through closure
+    /// Same as Pack::update(), data is changed
+    /// only if save succeeds.
+    pub fn update<F, R>(&self, f: F) -> PackResult<R>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        let mut guard = self.write();
+        let res = f(&mut guard);
+        guard.commit()?;
+        Ok(res)
+    }
+    /// Returns Pack<T>
+    /// &Path
+    pub fn get_path(&self) -> PathBuf {
+        self.inner.read_pack().path.clone()
+    }
+    /// Returns the inner Pack<T>
+    /// if there is no other handle
+    pub fn try_unwrap(self) -> Result<Pack<T>, Self> {
+        match Arc::try_unwrap(self.inner) {
+            Ok(inner) => {
+                Ok(inner.pack.into_inner().unwrap_or_else(|e| e.into_inner()))
+            }
+            Err(inner) => Err(SharedPack { inner }),
+        }
+    }
+}
+
+impl<T> SharedPack<T>
+where
+    for<'de> T: Serialize + Deserialize<'de> + Sized + Clone,
+{
+    /// Reload T from FS if the packfile has been
+    /// changed since the last load or save.
+    /// Returns the old T if it has been reloaded.
+    pub fn reload(&self) -> PackResult<Option<T>> {
+        let _write_lock = self
+            .inner
+            .write_lock
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if !self.inner.read_pack().is_stale()? {
+            return Ok(None);
+        }
+        let (data, checksum, version) = load_data_object(&self.get_path())?;
+        let mut pack =
+            self.inner.pack.write().unwrap_or_else(|e| e.into_inner());
+        let old = std::mem::replace(&mut pack.data, data);
+        pack.save_state.set_loaded(checksum, version);
+        Ok(Some(old))
+    }
+}
+
+impl<T> SharedPack<T>
+where
+    for<'de> T:
+        Serialize + Deserialize<'de> + Sized + Clone + Send + Sync + 'static,
+{
+    /// Start a watcher thread, that checks the packfile
+    /// in every interval, and reloads T if it is stale.
+    /// After a reload, on_change is called with the
+    /// old and the new T. Reload errors are logged.
+    pub fn watch<F>(&self, interval: Duration, mut on_change: F) -> PackWatcher
+    where
+        F: FnMut(&T, &T) + Send + 'static,
+    {
+        let (stop, stopped) = channel::<()>();
+        let shared = self.clone();
+        let worker = std::thread::spawn(move || loop {
+            match stopped.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => match shared.reload() {
+                    Ok(Some(old)) => {
+                        let new = shared.read().clone();
+                        on_change(&old, &new);
+                    }
+                    Ok(None) => (),
+                    Err(err) => log::warn!(
+                        "Error while reloading {:?}: {}",
+                        shared.get_path(),
+                        err
+                    ),
+                },
+                // Stop or watcher dropped
+                _ => return,
+            }
+        });
+        PackWatcher {
+            stop: Some(stop),
+            worker: Some(worker),
+        }
+    }
+}
+
+impl<T> SharedPack<T>
+where
+    T: Serialize + Sized + Clone + Send + 'static,
+{
+    /// Subscribe a callback to the changes of Pack<T>
+    /// See Pack::subscribe()
+    pub fn subscribe<F>(
+        &self,
+        with_values: bool,
+        callback: F,
+    ) -> observer::Subscription
+    where
+        F: Fn(&observer::ChangeEvent<T>) + Send + Sync + 'static,
+    {
+        self.inner.read_pack().subscribe(with_values, callback)
+    }
+    /// Subscribe a channel to the changes of Pack<T>
+    /// See Pack::subscribe_channel()
+    pub fn subscribe_channel(
+        &self,
+        with_values: bool,
+    ) -> (observer::Subscription, Receiver<observer::ChangeEvent<T>>) {
+        self.inner.read_pack().subscribe_channel(with_values)
+    }
+}
+
+/// PackWatcher
+/// Owns the watcher thread of a SharedPack<T>
+/// When dropped, it stops the thread.
+pub struct PackWatcher {
+    stop: Option<Sender<()>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl PackWatcher {
+    /// Stop watching
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+    fn shutdown(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for PackWatcher {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl<T> SharedInner<T>
+where
+    T: Serialize + Sized + Clone,
+{
+    fn read_pack(&self) -> RwLockReadGuard<'_, Pack<T>> {
+        self.pack.read().unwrap_or_else(|e| e.into_inner())
+    }
+    fn write_pack(&self) -> RwLockWriteGuard<'_, Pack<T>> {
+        self.pack.write().unwrap_or_else(|e| e.into_inner())
+    }
+    // Save new data, and replace the shared one
+    // Readers can read the old data during save
+    // Returns the old data and the change kind if it is saved
+    fn save(&self, data: &T) -> PackResult<Option<(T, observer::ChangeKind)>> {
+        let bytes = serialize_data_object(data)?;
+        // Members of a SharedVecPack<T> are checked and
+        // re-keyed in its indexes
+        let member_index = self.read_pack().member_index.clone();
+        let kind = match member_index {
+            Some(member_index) => {
+                let old = self.read_pack().data.clone();
+                member_index.change(&old, data, || self.write(data, bytes))?
+            }
+            None => self.write(data, bytes)?,
+        };
+        let kind = match kind {
+            Some(kind) => kind,
+            None => return Ok(None),
+        };
+        let old = std::mem::replace(&mut self.write_pack().data, data.clone());
+        Ok(Some((old, kind)))
+    }
+    // Write the packfile of new data
+    // Returns the change kind, or None if save is skipped
+    fn write(
+        &self,
+        data: &T,
+        bytes: Vec<u8>,
+    ) -> PackResult<Option<observer::ChangeKind>> {
+        // An ID change moves the member file first,
+        // and it is moved back if the save fails
+        let moved_from = {
+            let mut pack = self.write_pack();
+            let path = pack.id_path(data);
+            pack.move_for_id(path)?
+        };
+        let saved = self.read_pack().save_bytes(bytes);
+        if saved.is_err() {
+            self.write_pack().move_back(moved_from);
+        }
+        saved
+    }
+}
+
+impl<'a, T> Deref for SharedPackReadGuard<'a, T>
+where
+    T: Serialize + Sized + Clone,
+{
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard.data
+    }
+}
+
+impl<'a, T> Deref for SharedPackWriteGuard<'a, T>
+where
+    T: Serialize + Sized + Clone,
+{
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.data
+    }
+}
+
+impl<'a, T> DerefMut for SharedPackWriteGuard<'a, T>
+where
+    T: Serialize + Sized + Clone,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.dirty = true;
+        &mut self.data
+    }
+}
+
+impl<'a, T> SharedPackWriteGuard<'a, T>
+where
+    T: Serialize + Sized + Clone,
+{
+    /// Save changes and return the save result
+    /// If save fails, then the shared data is not changed.
+    pub fn commit(mut self) -> PackResult<()> {
+        self.finished = true;
+        self.release()
+    }
+    /// Discard changes without saving
+    pub fn abort(mut self) {
+        self.finished = true;
+    }
+    fn release(&mut self) -> PackResult<()> {
+        if !self.dirty {
+            self.inner.read_pack().save_state.set_skipped();
+            return Ok(());
+        }
+        let (old, kind) = match self.inner.save(&self.data)? {
+            Some(saved) => saved,
+            None => return Ok(()),
+        };
+        let notice = self.inner.read_pack().notice(kind, Some(&self.data));
+        // Listeners are called after the pack is unlocked,
+        // so they can read or write it again
+        self.write_lock.take();
+        notice.send(Some(&old), Some(&self.data));
+        Ok(())
+    }
+}
+
+impl<'a, T> Drop for SharedPackWriteGuard<'a, T>
+where
+    T: Serialize + Sized + Clone,
+{
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        if let Err(err) = self.release() {
+            let pack = self.inner.read_pack();
+            pack.report_drop_error(FailedSave {
+                path: pack.path.clone(),
+                bytes: serialize_data_object(&self.data).unwrap_or_default(),
+                workspace_id: pack.options.workspace_id,
+                version: pack.version(),
+                error: err,
+            });
+        }
+    }
+}
+
+/// SharedVecPack<T>
+/// Cloneable, thread-safe handle of a VecPack<T>
+/// Every member is a SharedPack<T>, so members are
+/// locked one by one; the collection itself is only
+/// locked exclusively by insert and remove.
+pub struct SharedVecPack<T>
+where
+    T: VecPackMember,
+{
+    inner: Arc<RwLock<SharedVecInner<T>>>,
+}
+
+struct SharedVecInner<T>
+where
+    T: VecPackMember,
+{
+    path: PathBuf,
+    layout: layout::Layout,
+    options: PackOptions,
+    observers: observer::Observers<T>,
+    // Member ID and secondary indexes
+    // Locked after the collection, and before a member
+    index: index::IdIndex<T>,
+    items: Vec<SharedPack<T>>,
+}
+
+impl<T> Clone for SharedVecPack<T>
+where
+    T: VecPackMember,
+{
+    fn clone(&self) -> Self {
+        SharedVecPack {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> VecPack<T>
+where
+    T: VecPackMember,
+{
+    /// Turn VecPack<T> into a
+    /// thread-safe SharedVecPack<T>
+    /// The indexes are kept, so secondary index
+    /// keys are checked on every member save.
+    pub fn into_shared(self) -> SharedVecPack<T> {
+        self.index.refresh(self.data.iter().map(|pack| &pack.data));
+        SharedVecPack {
+            inner: Arc::new(RwLock::new(SharedVecInner {
+                path: self.path,
+                layout: self.layout,
+                options: self.options,
+                observers: self.observers,
+                index: self.index,
+                items: self.data.into_iter().map(SharedPack::new).collect(),
+            })),
+        }
+    }
+}
+
+impl<T> SharedVecPack<T>
+where
+    T: VecPackMember,
+{
+    /// Number of members
+    pub fn len(&self) -> usize {
+        self.read().items.len()
+    }
+    /// True if there is no member
+    pub fn is_empty(&self) -> bool {
+        self.read().items.is_empty()
+    }
+    /// Find member by ID
+    /// and returns its shared handle
+    pub fn find_id(
+        &self,
+        id: &<T as VecPackMember>::Out,
+    ) -> PackResult<SharedPack<T>> {
+        let inner = self.read();
+        match inner.position(id) {
+            Some(position) => Ok(inner.items[position].clone()),
+            None => Err(PackError::ObjectNotFound),
+        }
+    }
+    /// Find members by a secondary index key
+    /// Returns PackError::ObjectNotFound if there
+    /// is no index with the given name.
+    pub fn find_by(
+        &self,
+        index: &str,
+        key: &str,
+    ) -> PackResult<Vec<SharedPack<T>>> {
+        let inner = self.read();
+        inner.refresh_index();
+        Ok(inner
+            .index
+            .find_by(index, key)?
+            .into_iter()
+            .filter_map(|position| inner.items.get(position).cloned())
+            .collect())
+    }
+    /// Returns the shared handle
+    /// of every member
+    pub fn items(&self) -> Vec<SharedPack<T>> {
+        self.read().items.clone()
+    }
+    /// Insert a new T
+    /// Only if ID is not taken
+    pub fn insert(&self, item: T) -> PackResult<SharedPack<T>> {
+        let mut inner = self.inner.write().unwrap_or_else(|e| e.into_inner());
+        if inner.options.read_only {
+            return Err(PackError::ReadOnly);
+        }
+        inner.refresh_index();
+        let path = inner.layout.new_member_path(&inner.path, item.get_id())?;
+        let mut pack = Pack::from_data(item, path);
+        pack.options = inner.options.clone();
+        inner.index.insert_with(&pack.data, inner.items.len(), || {
+            pack.write_data_object(serialize_data_object(&pack.data)?)
+                .map(drop)
+        })?;
+        pack.parent_observers = Some(inner.observers.clone());
+        pack.member_index = Some(inner.index.clone());
+        pack.member_id = Some(member_id::<T>);
+        pack.member_layout = Some((inner.path.clone(), inner.layout));
+        let notice =
+            pack.notice(observer::ChangeKind::Inserted, Some(&pack.data));
+        let data = (!notice.is_empty()).then(|| pack.data.clone());
+        let shared = SharedPack::new(pack);
+        inner.items.push(shared.clone());
+        // Listeners are called after the collection is
+        // unlocked, so they can use SharedVecPack<T>
+        drop(inner);
+        if let Some(data) = data {
+            notice.send(None, Some(&data));
+        }
+        Ok(shared)
+    }
+    /// Remove member by ID
+    /// and returns its last data
+    /// Waits for the active writer of the member. Handles of
+    /// the member cloned before are detached: saving them
+    /// returns PackError::ReadOnly.
+    pub fn remove(&self, id: &<T as VecPackMember>::Out) -> PackResult<T> {
+        loop {
+            let mut inner =
+                self.inner.write().unwrap_or_else(|e| e.into_inner());
+            if inner.options.read_only {
+                return Err(PackError::ReadOnly);
+            }
+            let position = match inner.position(id) {
+                Some(position) => position,
+                None => return Err(PackError::ObjectNotFound),
+            };
+            let item = inner.items[position].clone();
+            // The collection is locked before a member, so
+            // the active writer of the member can still use
+            // the collection; it is waited without the lock.
+            let write_lock = match item.inner.write_lock.try_lock() {
+                Ok(write_lock) => write_lock,
+                Err(TryLockError::Poisoned(e)) => e.into_inner(),
+                Err(TryLockError::WouldBlock) => {
+                    drop(inner);
+                    drop(item.inner.write_lock.lock());
+                    continue;
+                }
+            };
+            let (data, notice) = {
+                // Pending background save would
+                // create the file again
+                let mut pack = item.inner.write_pack();
+                pack.flush()?;
+                fs::remove_file(&pack.path)?;
+                pack.file.close();
+                pack.options.read_only = true;
+                pack.member_index = None;
+                let notice = pack
+                    .notice(observer::ChangeKind::Removed, Some(&pack.data));
+                (pack.data.clone(), notice)
+            };
+            inner.index.remove(&data, position);
+            inner.items.remove(position);
+            // Listeners are called after the
+            // collection and the member are unlocked
+            drop(write_lock);
+            drop(inner);
+            notice.send(Some(&data), None);
+            return Ok(data);
+        }
+    }
+    /// Subscribe a callback to every insert, remove
+    /// and member save. See VecPack::subscribe()
+    pub fn subscribe<F>(
+        &self,
+        with_values: bool,
+        callback: F,
+    ) -> observer::Subscription
+    where
+        T: Send + 'static,
+        F: Fn(&observer::ChangeEvent<T>) + Send + Sync + 'static,
+    {
+        self.read().observers.subscribe(with_values, callback)
+    }
+    /// Subscribe a channel to every insert, remove
+    /// and member save. See VecPack::subscribe_channel()
+    pub fn subscribe_channel(
+        &self,
+        with_values: bool,
+    ) -> (observer::Subscription, Receiver<observer::ChangeEvent<T>>)
+    where
+        T: Send + 'static,
+    {
+        self.read().observers.subscribe_channel(with_values)
+    }
+    fn read(&self) -> RwLockReadGuard<'_, SharedVecInner<T>> {
+        self.inner.read().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<T> SharedVecInner<T>
+where
+    T: VecPackMember,
+{
+    // Position of a member by its ID
+    // The index is built again if it is stale,
+    // or it does not match the members
+    fn position(&self, id: &<T as VecPackMember>::Out) -> Option<usize> {
+        let key = id.to_string();
+        let matches = |position: usize| {
+            self.items
+                .get(position)
+                .is_some_and(|item| item.read().get_id() == id)
+        };
+        if !self.index.is_stale() {
+            match self.index.get(&key) {
+                Some(position) if matches(position) => return Some(position),
+                None => return None,
+                Some(_) => (),
+            }
+        }
+        self.index.rebuild(self.data().iter());
+        self.index.get(&key).filter(|position| matches(*position))
+    }
+    // Build the indexes again if they are stale
+    fn refresh_index(&self) {
+        if self.index.is_stale() {
+            self.index.rebuild(self.data().iter());
+        }
+    }
+    // Copy of the member data, so members
+    // are not locked while the index is
+    fn data(&self) -> Vec<T> {
+        self.items.iter().map(|item| item.read().clone()).collect()
+    }
+}
@@ -0,0 +1,678 @@
+//! Transactions
+//!
+//! A Transaction stages changes of several Pack<T> objects and
+//! VecPack<T> members, and commits them all or none. Before the first
+//! packfile is written, every new data is written into an intent log
+//! file. If the process crashes during commit, the next
+//! Transaction::recover() (called by Workspace::load_or_init) replays
+//! the log, so every staged change is applied.
+
+use crate::*;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Intent log file extension
+// Logs are first written with TMP_EXT, and renamed
+// when complete; so incomplete logs are never replayed.
+const LOG_EXT: &str = "log";
+const TMP_EXT: &str = "tmp";
+
+// Intent log of a transaction
+#[derive(Serialize, Deserialize)]
+struct IntentLog {
+    entries: Vec<IntentEntry>,
+}
+
+// Staged packfile write or remove
+// In the log file, path is relative to the parent of the
+// log directory (the workspace root) when it is inside it
+#[derive(Serialize, Deserialize, Clone)]
+struct IntentEntry {
+    path: PathBuf,
+    workspace_id: Option<u64>,
+    op: IntentOp,
+    // Packfile version before the commit, None if there is
+    // no packfile. Replay skips the entry if the packfile
+    // has an other version, as it is already applied.
+    version: Option<u64>,
+    bytes: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+enum IntentOp {
+    Write,
+    Remove,
+}
+
+// Intent log entry, and the packfile bytes to put
+// back if the commit fails; None if there is no
+// packfile to put back
+struct StagedEntry {
+    entry: IntentEntry,
+    backup: Option<Vec<u8>>,
+}
+
+// Staged changes of a single Pack<T> or VecPack<T>
+// Type erased, so a transaction can hold packs
+// of different types.
+trait Staged {
+    // Number of staged changes
+    fn len(&self) -> usize;
+    // Packfile writes and removes of the changes
+    // One entry per path
+    fn entries(&self) -> Vec<StagedEntry>;
+    // Move staged data into the packs after the packfiles
+    // are written. Versions are the new packfile versions.
+    fn apply(&mut self, versions: &HashMap<PathBuf, u64>);
+}
+
+struct StagedPack<'a, T>
+where
+    T: Serialize + Sized + Clone,
+{
+    pack: &'a mut Pack<T>,
+    data: Option<T>,
+    // Path of the new data, an ID change
+    // writes a new member file
+    path: PathBuf,
+    bytes: Vec<u8>,
+    backup: Vec<u8>,
+}
+
+impl<'a, T> Staged for StagedPack<'a, T>
+where
+    T: Serialize + Sized + Clone,
+{
+    fn len(&self) -> usize {
+        1
+    }
+    fn entries(&self) -> Vec<StagedEntry> {
+        let workspace_id = self.pack.options.workspace_id;
+        let moved = self.path != self.pack.path;
+        let mut entries = vec![StagedEntry {
+            entry: write_intent(&self.path, workspace_id, &self.bytes),
+            backup: (!moved).then(|| self.backup.clone()),
+        }];
+        // An ID change writes the file of the new ID,
+        // then removes the old one
+        if moved {
+            entries.push(StagedEntry {
+                entry: remove_intent(&self.pack.path, workspace_id),
+                backup: Some(self.backup.clone()),
+            });
+        }
+        entries
+    }
+    fn apply(&mut self, versions: &HashMap<PathBuf, u64>) {
+        self.pack.path = self.path.clone();
+        let old = self
+            .data
+            .take()
+            .map(|data| std::mem::replace(&mut self.pack.data, data));
+        self.pack.save_state.set_saved(crc32fast::hash(&self.bytes));
+        if let Some(version) = versions.get(&self.path) {
+            self.pack.save_state.set_version(*version);
+        }
+        self.pack.rekey_id(old.as_ref());
+        self.pack.notify(
+            observer::ChangeKind::Saved,
+            old.as_ref(),
+            Some(&self.pack.data),
+        );
+    }
+}
+
+// Staged data of a VecPack<T> member
+struct StagedMember<T> {
+    data: T,
+    path: PathBuf,
+    bytes: Vec<u8>,
+}
+
+/// StagedVecPack<'a, T>
+/// Inserts, updates and removes of a VecPack<T> staged in
+/// a Transaction. Members are staged by their ID, so any
+/// number of them can be changed by one transaction. IDs and
+/// unique index keys are checked against the staged members.
+pub struct StagedVecPack<'a, T>
+where
+    T: VecPackMember,
+{
+    vecpack: &'a mut VecPack<T>,
+    // Copy of the VecPack<T> indexes with the staged
+    // changes; positions after the members are the
+    // positions of the staged inserts
+    index: index::IdIndex<T>,
+    // Staged members by position, None if removed
+    changes: BTreeMap<usize, Option<StagedMember<T>>>,
+    // Staged inserts, None if removed again
+    inserts: Vec<Option<StagedMember<T>>>,
+}
+
+impl<'a, T> StagedVecPack<'a, T>
+where
+    T: VecPackMember,
+{
+    /// Stage an insert
+    /// The member file is written on commit.
+    pub fn insert(&mut self, item: T) -> PackResult<()> {
+        if self.index.get(&item.get_id().to_string()).is_some() {
+            return Err(PackError::IDTaken);
+        }
+        self.index.check_insert(&item)?;
+        let path = self
+            .vecpack
+            .layout
+            .new_member_path(&self.vecpack.path, item.get_id())?;
+        let position = self.vecpack.data.len() + self.inserts.len();
+        self.index.insert(&item, position);
+        self.inserts.push(Some(StagedMember {
+            bytes: serialize_data_object(&item)?,
+            data: item,
+            path,
+        }));
+        Ok(())
+    }
+    /// Stage an update of a member by ID
+    /// The closure is applied to a copy of the staged
+    /// member, and returns its result. If it changes the
+    /// ID, then the member file is renamed by the commit.
+    /// Returns PackError::ObjectNotFound if there is no
+    /// member with ID.
+    pub fn update<F, R>(
+        &mut self,
+        id: &<T as VecPackMember>::Out,
+        f: F,
+    ) -> PackResult<R>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        let position = self.position(id)?;
+        let old = self.staged(position);
+        let mut data = old.clone();
+        let res = f(&mut data);
+        self.index.check(old, &data)?;
+        self.index.changed(old, &data);
+        let path = self
+            .vecpack
+            .layout
+            .new_member_path(&self.vecpack.path, data.get_id())?;
+        let member = Some(StagedMember {
+            bytes: serialize_data_object(&data)?,
+            data,
+            path,
+        });
+        match position.checked_sub(self.vecpack.data.len()) {
+            Some(insert) => self.inserts[insert] = member,
+            None => {
+                // Commit writes the packfile by its path
+                self.vecpack.data[position].file.close();
+                self.changes.insert(position, member);
+            }
+        }
+        Ok(res)
+    }
+    /// Stage a remove of a member by ID
+    /// The member file is removed on commit.
+    /// Returns PackError::ObjectNotFound if there
+    /// is no member with ID.
+    pub fn remove(&mut self, id: &<T as VecPackMember>::Out) -> PackResult<()> {
+        let position = self.position(id)?;
+        self.index.forget(self.staged(position), position);
+        match position.checked_sub(self.vecpack.data.len()) {
+            Some(insert) => self.inserts[insert] = None,
+            None => {
+                self.vecpack.data[position].file.close();
+                self.changes.insert(position, None);
+            }
+        }
+        Ok(())
+    }
+    // Position of a staged member by its ID
+    fn position(&self, id: &<T as VecPackMember>::Out) -> PackResult<usize> {
+        self.index
+            .get(&id.to_string())
+            .ok_or(PackError::ObjectNotFound)
+    }
+    // Staged data of the member at position
+    // The position must be taken from the index
+    fn staged(&self, position: usize) -> &T {
+        let member = match position.checked_sub(self.vecpack.data.len()) {
+            Some(insert) => self.inserts[insert].as_ref(),
+            None => match self.changes.get(&position) {
+                Some(member) => member.as_ref(),
+                None => return &self.vecpack.data[position].data,
+            },
+        };
+        &member.expect("indexed member is staged").data
+    }
+}
+
+impl<'a, T> Staged for StagedVecPack<'a, T>
+where
+    for<'de> T: VecPackMember + Deserialize<'de>,
+{
+    fn len(&self) -> usize {
+        self.changes.len() + self.inserts.iter().flatten().count()
+    }
+    fn entries(&self) -> Vec<StagedEntry> {
+        let workspace_id = self.vecpack.options.workspace_id;
+        // Path -> bytes to write, None to remove
+        let mut paths: BTreeMap<&PathBuf, Option<&[u8]>> = BTreeMap::new();
+        for position in self.changes.keys() {
+            paths
+                .entry(&self.vecpack.data[*position].path)
+                .or_insert(None);
+        }
+        let written = self.changes.values().chain(self.inserts.iter());
+        for member in written.flatten() {
+            paths.insert(&member.path, Some(&member.bytes));
+        }
+        paths
+            .into_iter()
+            .map(|(path, bytes)| {
+                // Member file is put back if there is one
+                let backup = self
+                    .vecpack
+                    .data
+                    .iter()
+                    .find(|pack| &pack.path == path)
+                    .and_then(|pack| serialize_data_object(&pack.data).ok());
+                let entry = match bytes {
+                    Some(bytes) => write_intent(path, workspace_id, bytes),
+                    None => remove_intent(path, workspace_id),
+                };
+                StagedEntry { entry, backup }
+            })
+            .collect()
+    }
+    fn apply(&mut self, versions: &HashMap<PathBuf, u64>) {
+        let saved = |pack: &mut Pack<T>, path: &PathBuf, bytes: &[u8]| {
+            pack.save_state.set_saved(crc32fast::hash(bytes));
+            if let Some(version) = versions.get(path) {
+                pack.save_state.set_version(*version);
+            }
+        };
+        let changes = std::mem::take(&mut self.changes);
+        let removed_positions: Vec<usize> = changes
+            .iter()
+            .filter(|(_, member)| member.is_none())
+            .map(|(position, _)| *position)
+            .collect();
+        // Removed members are taken from the end,
+        // so the positions before them are kept
+        let removed: Vec<Pack<T>> = removed_positions
+            .iter()
+            .rev()
+            .map(|position| self.vecpack.take(*position))
+            .collect();
+        let mut updated = Vec::new();
+        for (position, member) in changes.into_iter() {
+            let member = match member {
+                Some(member) => member,
+                None => continue,
+            };
+            // Shifted by the removed members before it
+            let position = position
+                - removed_positions.iter().filter(|p| **p < position).count();
+            let pack = &mut self.vecpack.data[position];
+            saved(pack, &member.path, &member.bytes);
+            pack.path = member.path;
+            let old = std::mem::replace(&mut pack.data, member.data);
+            updated.push((position, old));
+        }
+        // Updated IDs and keys could be swapped,
+        // so the indexes are built again
+        if !updated.is_empty() {
+            self.vecpack.index.set_stale();
+        }
+        let mut inserted = Vec::new();
+        for member in std::mem::take(&mut self.inserts).into_iter().flatten() {
+            let mut pack = Pack::from_data(member.data, member.path.clone());
+            pack.options = self.vecpack.options.clone();
+            saved(&mut pack, &member.path, &member.bytes);
+            self.vecpack.adopt(&mut pack);
+            inserted.push(self.vecpack.data.len());
+            self.vecpack.push(pack);
+        }
+        for pack in removed.iter().rev() {
+            pack.notify(observer::ChangeKind::Removed, Some(&pack.data), None);
+        }
+        for (position, old) in updated {
+            let pack = &self.vecpack.data[position];
+            pack.notify(
+                observer::ChangeKind::Saved,
+                Some(&old),
+                Some(&pack.data),
+            );
+        }
+        for position in inserted {
+            let pack = &self.vecpack.data[position];
+            pack.notify(observer::ChangeKind::Inserted, None, Some(&pack.data));
+        }
+    }
+}
+
+/// Transaction
+/// Stages changes of several packs, and commits them
+/// all or none. Dropping a transaction without commit
+/// discards every staged change.
+///
+/// ```rust,no_run
+/// use packman::workspace::Workspace;
+/// use packman::*;
+/// # use std::path::PathBuf;
+/// let mut ws = Workspace::load_or_init(PathBuf::from("data/repo"), 1)?;
+/// let mut from: Pack<u32> = ws.file_from("warehouse_a")?;
+/// let mut to: Pack<u32> = ws.file_from("warehouse_b")?;
+/// let mut tx = ws.transaction();
+/// tx.update(&mut from, |stock| *stock -= 5)?;
+/// tx.update(&mut to, |stock| *stock += 5)?;
+/// tx.commit()?;
+/// # Ok::<(), PackError>(())
+/// ```
+///
+/// A staged change borrows its pack until the transaction
+/// is committed or dropped, so a Pack<T> is updated once per
+/// transaction, and the changes of a VecPack<T> are staged
+/// together by Transaction::vecpack().
+pub struct Transaction<'a> {
+    log_dir: PathBuf,
+    staged: Vec<Box<dyn Staged + 'a>>,
+}
+
+impl<'a> Transaction<'a> {
+    /// New empty transaction
+    /// Its intent log is written into log_dir
+    pub fn new(log_dir: PathBuf) -> Self {
+        Transaction {
+            log_dir,
+            staged: Vec::new(),
+        }
+    }
+    /// Stage an update of Pack<T>
+    /// The closure is applied to a copy of T, and
+    /// Pack<T> is only changed when the transaction
+    /// is committed. Returns the closure result.
+    /// If it changes the ID of a VecPack<T> member, then
+    /// the member file is renamed by the commit.
+    pub fn update<T, F, R>(
+        &mut self,
+        pack: &'a mut Pack<T>,
+        f: F,
+    ) -> PackResult<R>
+    where
+        T: Serialize + Sized + Clone,
+        F: FnOnce(&mut T) -> R,
+    {
+        if pack.is_read_only() {
+            return Err(PackError::ReadOnly);
+        }
+        // Pending background save must not
+        // overwrite the committed data later
+        pack.flush()?;
+        // Commit writes the packfile by its path
+        pack.file.close();
+        let mut data = pack.data.clone();
+        let res = f(&mut data);
+        pack.check_id_change(Some(&pack.data), &data)?;
+        let path = match pack.id_path(&data) {
+            Some(path) => {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                path
+            }
+            None => pack.path.clone(),
+        };
+        self.staged.push(Box::new(StagedPack {
+            bytes: serialize_data_object(&data)?,
+            backup: serialize_data_object(&pack.data)?,
+            pack,
+            data: Some(data),
+            path,
+        }));
+        Ok(res)
+    }
+    /// Stage changes of VecPack<T>
+    /// The closure stages inserts, updates and removes
+    /// through StagedVecPack<T>, and returns its result.
+    /// If it returns an error, then nothing is staged
+    /// for VecPack<T>.
+    ///
+    /// ```rust,no_run
+    /// use packman::workspace::Workspace;
+    /// use packman::*;
+    /// # use serde::{Deserialize, Serialize};
+    /// # use std::path::PathBuf;
+    /// # #[derive(Serialize, Deserialize, Clone)]
+    /// # struct Warehouse { id: u32, stock: u32 }
+    /// # impl VecPackMember for Warehouse {
+    /// #     type Out = u32;
+    /// #     fn get_id(&self) -> &u32 { &self.id }
+    /// # }
+    /// let mut ws = Workspace::load_or_init(PathBuf::from("data/repo"), 1)?;
+    /// let mut warehouses: VecPack<Warehouse> = ws.folder_from("warehouses")?;
+    /// let mut tx = ws.transaction();
+    /// tx.vecpack(&mut warehouses, |warehouses| {
+    ///     warehouses.update(&1, |w| w.stock -= 5)?;
+    ///     warehouses.update(&2, |w| w.stock += 5)
+    /// })?;
+    /// tx.commit()?;
+    /// # Ok::<(), PackError>(())
+    /// ```
+    pub fn vecpack<T, F, R>(
+        &mut self,
+        vecpack: &'a mut VecPack<T>,
+        f: F,
+    ) -> PackResult<R>
+    where
+        for<'de> T: VecPackMember + Deserialize<'de> + 'a,
+        F: FnOnce(&mut StagedVecPack<'a, T>) -> PackResult<R>,
+    {
+        if vecpack.options.read_only {
+            return Err(PackError::ReadOnly);
+        }
+        vecpack.flush()?;
+        vecpack.refresh_index();
+        let mut staged = StagedVecPack {
+            index: vecpack.index.snapshot(),
+            vecpack,
+            changes: BTreeMap::new(),
+            inserts: Vec::new(),
+        };
+        let res = f(&mut staged)?;
+        self.staged.push(Box::new(staged));
+        Ok(res)
+    }
+    /// Number of staged changes
+    pub fn len(&self) -> usize {
+        self.staged.iter().map(|staged| staged.len()).sum()
+    }
+    /// True if nothing is staged
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Commit every staged change
+    /// If a packfile write or remove fails, then the already written
+    /// ones are restored, and the error is returned. If even
+    /// the restore fails, then the intent log is kept, and the
+    /// next recover() applies every change.
+    pub fn commit(mut self) -> PackResult<()> {
+        let mut entries: Vec<StagedEntry> = self
+            .staged
+            .iter()
+            .flat_map(|staged| staged.entries())
+            .collect();
+        if entries.is_empty() {
+            return Ok(());
+        }
+        for staged in entries.iter_mut() {
+            staged.entry.version = file_version(&staged.entry.path)?;
+        }
+        let log_path = self.write_log(&entries)?;
+        let mut versions = HashMap::new();
+        for (written, staged) in entries.iter().enumerate() {
+            match write_entry(&staged.entry, &staged.entry.path) {
+                Ok(version) => {
+                    versions.insert(staged.entry.path.clone(), version);
+                }
+                Err(err) => {
+                    let restored = entries[..written]
+                        .iter()
+                        .all(|staged| restore(staged).is_ok());
+                    if restored {
+                        std::fs::remove_file(&log_path)?;
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        std::fs::remove_file(&log_path)?;
+        for staged in self.staged.iter_mut() {
+            staged.apply(&versions);
+        }
+        Ok(())
+    }
+    /// Discard every staged change
+    pub fn abort(self) {}
+    /// Replay every complete intent log in log_dir
+    /// then remove them. Incomplete logs are removed
+    /// without replay. Returns the number of replayed
+    /// transactions.
+    pub fn recover(log_dir: &Path) -> PackResult<usize> {
+        if !log_dir.exists() {
+            return Ok(0);
+        }
+        let root = log_root(log_dir);
+        let mut replayed = 0;
+        for entry in std::fs::read_dir(log_dir)? {
+            let path = entry?.path();
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some(LOG_EXT) => {
+                    let log: IntentLog =
+                        serde_json::from_slice(&std::fs::read(&path)?)?;
+                    for entry in &log.entries {
+                        let path = root.join(&entry.path);
+                        // Entries written before the crash are skipped
+                        if file_version(&path)? == entry.version {
+                            write_entry(entry, &path)?;
+                        }
+                    }
+                    std::fs::remove_file(&path)?;
+                    replayed += 1;
+                }
+                Some(TMP_EXT) => std::fs::remove_file(&path)?,
+                _ => (),
+            }
+        }
+        Ok(replayed)
+    }
+    // Write intent log, and returns its path
+    fn write_log(&self, entries: &[StagedEntry]) -> PackResult<PathBuf> {
+        std::fs::create_dir_all(&self.log_dir)?;
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        let name = format!("{}-{}", std::process::id(), nanos);
+        let root = log_root(&self.log_dir);
+        let log = IntentLog {
+            entries: entries
+                .iter()
+                .map(|staged| {
+                    let entry = &staged.entry;
+                    // Outside of root we need an absolute path,
+                    // as recover() may run from an other directory
+                    let path = match entry.path.strip_prefix(root) {
+                        Ok(path) => path.to_path_buf(),
+                        Err(_) => std::fs::canonicalize(&entry.path)
+                            .unwrap_or_else(|_| entry.path.clone()),
+                    };
+                    IntentEntry {
+                        path,
+                        ..entry.clone()
+                    }
+                })
+                .collect(),
+        };
+        let tmp_path = self.log_dir.join(format!("{}.{}", name, TMP_EXT));
+        let log_path = self.log_dir.join(format!("{}.{}", name, LOG_EXT));
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(&serde_json::to_vec(&log)?)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, &log_path)?;
+        Ok(log_path)
+    }
+}
+
+fn write_intent(
+    path: &Path,
+    workspace_id: Option<u64>,
+    bytes: &[u8],
+) -> IntentEntry {
+    IntentEntry {
+        path: path.to_path_buf(),
+        workspace_id,
+        op: IntentOp::Write,
+        version: None,
+        bytes: bytes.to_vec(),
+    }
+}
+
+fn remove_intent(path: &Path, workspace_id: Option<u64>) -> IntentEntry {
+    IntentEntry {
+        path: path.to_path_buf(),
+        workspace_id,
+        op: IntentOp::Remove,
+        version: None,
+        bytes: Vec::new(),
+    }
+}
+
+// Version of the packfile at path
+// None if there is no packfile
+fn file_version(path: &Path) -> PackResult<Option<u64>> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let pack_file = fs::PackFile::open_read_only(path)?;
+    Ok(Some(pack_file.metadata().file_version))
+}
+
+// Write or remove the packfile of an entry at path
+// Returns the new packfile version, or 0 if removed.
+fn write_entry(entry: &IntentEntry, path: &PathBuf) -> PackResult<u64> {
+    match entry.op {
+        IntentOp::Write => {
+            save_data_object(path, &entry.bytes, entry.workspace_id)
+        }
+        IntentOp::Remove => match std::fs::remove_file(path) {
+            Ok(_) => Ok(0),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e.into()),
+        },
+    }
+}
+
+// Put back the packfile of a written or removed entry
+fn restore(staged: &StagedEntry) -> PackResult<()> {
+    let entry = &staged.entry;
+    match &staged.backup {
+        Some(bytes) => {
+            save_data_object(&entry.path, bytes, entry.workspace_id)?;
+        }
+        None => {
+            if entry.path.exists() {
+                std::fs::remove_file(&entry.path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Intent log paths are relative to this
+fn log_root(log_dir: &Path) -> &Path {
+    log_dir.parent().unwrap_or_else(|| Path::new(""))
+}
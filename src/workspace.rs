@@ -0,0 +1,395 @@
+//! Workspace
+//!
+//! A root directory that owns a set of Pack<T> and VecPack<T>
+//! objects. Every packfile created through a workspace has the
+//! workspace ID in its superblock, and every object handed out
+//! is recorded in the workspace registry, so that the whole
+//! workspace can be verified, backed up or measured at once.
+
+use crate::*;
+use lazy::LazyPack;
+use std::path::Component;
+
+// Registry file name inside the workspace root
+const REGISTRY_FILE: &str = ".workspace";
+// Transaction intent log directory inside the workspace root
+const TRANSACTION_DIR: &str = ".transactions";
+
+/// Kind of a workspace object
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ObjectKind {
+    /// Single Pack<T> file
+    File,
+    /// VecPack<T> directory
+    Folder,
+}
+
+/// Registered workspace object
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct WorkspaceObject {
+    pub name: String,
+    pub kind: ObjectKind,
+}
+
+// Workspace registry
+// Stored as a Pack<Registry> in the workspace root
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct Registry {
+    id: u64,
+    objects: Vec<WorkspaceObject>,
+}
+
+/// Result of Workspace::verify()
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// Number of packfiles checked
+    pub checked: usize,
+    /// Every packfile that failed the check
+    pub errors: Vec<(PathBuf, PackError)>,
+}
+
+impl VerifyReport {
+    /// True if every packfile passed
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// Storage statistics of a single workspace object
+#[derive(Debug)]
+pub struct ObjectStats {
+    pub name: String,
+    pub kind: ObjectKind,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Result of Workspace::stats()
+#[derive(Debug, Default)]
+pub struct WorkspaceStats {
+    pub objects: Vec<ObjectStats>,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Workspace
+/// Root object managing a directory of packs
+///
+/// ```rust,no_run
+/// use packman::workspace::Workspace;
+/// use packman::*;
+/// # use std::path::PathBuf;
+/// let mut ws = Workspace::load_or_init(PathBuf::from("data/repo"), 1)?;
+/// let config: Pack<u32> = ws.file_from("config")?;
+/// # Ok::<(), PackError>(())
+/// ```
+pub struct Workspace {
+    root: PathBuf,
+    id: u64,
+    registry: Pack<Registry>,
+    read_only: bool,
+}
+
+impl Workspace {
+    /// Load or init workspace at the given root
+    /// If the root already holds a workspace with a different
+    /// ID, then returns PackError::WorkspaceMismatch.
+    /// Interrupted transactions are recovered.
+    pub fn load_or_init(root: PathBuf, id: u64) -> PackResult<Workspace> {
+        let is_new = !root.join(REGISTRY_FILE).exists();
+        let mut registry = Pack::<Registry>::load_or_init_in_workspace(
+            root.clone(),
+            REGISTRY_FILE,
+            Some(id),
+        )?;
+        if is_new {
+            registry.update(|r| r.id = id)?;
+        } else if registry.id != id {
+            return Err(PackError::WorkspaceMismatch(id, Some(registry.id)));
+        }
+        transaction::Transaction::recover(&root.join(TRANSACTION_DIR))?;
+        Ok(Workspace {
+            root,
+            id,
+            registry,
+            read_only: false,
+        })
+    }
+    /// Open an existing workspace in read-only mode
+    /// Nothing is written, so interrupted transactions
+    /// are not recovered, and only registered objects
+    /// can be loaded; every object is read-only.
+    pub fn open_read_only(root: PathBuf, id: u64) -> PackResult<Workspace> {
+        let registry =
+            Pack::<Registry>::load_read_only(root.join(REGISTRY_FILE))?;
+        if registry.id != id {
+            return Err(PackError::WorkspaceMismatch(id, Some(registry.id)));
+        }
+        Ok(Workspace {
+            root,
+            id,
+            registry,
+            read_only: true,
+        })
+    }
+    /// True if the workspace is opened in read-only mode
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+    /// Returns workspace ID
+    pub fn get_id(&self) -> u64 {
+        self.id
+    }
+    /// Returns workspace root
+    /// &Path
+    pub fn get_path(&self) -> &Path {
+        self.root.as_path()
+    }
+    /// New transaction
+    /// Its intent log is kept in the workspace
+    pub fn transaction<'a>(&self) -> transaction::Transaction<'a> {
+        transaction::Transaction::new(self.root.join(TRANSACTION_DIR))
+    }
+    /// Returns registered workspace objects
+    pub fn objects(&self) -> &[WorkspaceObject] {
+        &self.registry.objects
+    }
+    /// Load or init a Pack<T> by its name
+    /// relative to the workspace root, and register it
+    pub fn file_from<T>(&mut self, name: &str) -> PackResult<Pack<T>>
+    where
+        for<'de> T: Serialize + Deserialize<'de> + Default + Clone,
+    {
+        self.file_from_with(name, T::default)
+    }
+    /// Load or init a Pack<T> by its name, and register it
+    /// A new packfile is created with the data returned by init.
+    pub fn file_from_with<T, F>(
+        &mut self,
+        name: &str,
+        init: F,
+    ) -> PackResult<Pack<T>>
+    where
+        for<'de> T: Serialize + Deserialize<'de> + Clone,
+        F: FnOnce() -> T,
+    {
+        self.register(name, ObjectKind::File, |ws| {
+            let path = ws.root.join(name);
+            if ws.read_only {
+                return Pack::load_read_only(path);
+            }
+            let (dir, file_id) = match (path.parent(), path.file_name()) {
+                (Some(dir), Some(file_id)) => {
+                    (dir.to_path_buf(), file_id.to_string_lossy().to_string())
+                }
+                _ => return Err(PackError::PathNotFound),
+            };
+            Pack::load_or_init_in_workspace_with(
+                dir,
+                &file_id,
+                Some(ws.id),
+                init,
+            )
+        })
+    }
+    /// Open a LazyPack<T> by its name relative to the
+    /// workspace root, and register it. T is deserialized
+    /// on its first access; a new packfile is created
+    /// with T::default().
+    pub fn lazy_file_from<T>(&mut self, name: &str) -> PackResult<LazyPack<T>>
+    where
+        for<'de> T: Serialize + Deserialize<'de> + Default + Clone,
+    {
+        let path = self.root.join(name);
+        if !path.exists() {
+            return self.file_from(name).map(LazyPack::from);
+        }
+        self.register(name, ObjectKind::File, |ws| {
+            LazyPack::open_with(
+                path,
+                PackOptions {
+                    workspace_id: Some(ws.id),
+                    read_only: ws.read_only,
+                    ..PackOptions::default()
+                },
+            )
+        })
+    }
+    /// Load or init a VecPack<T> by its folder name
+    /// relative to the workspace root, and register it
+    pub fn folder_from<T>(&mut self, name: &str) -> PackResult<VecPack<T>>
+    where
+        for<'de> T: VecPackMember + Deserialize<'de>,
+    {
+        self.register(name, ObjectKind::Folder, |ws| {
+            if ws.read_only {
+                return VecPack::load_read_only(ws.root.join(name));
+            }
+            VecPack::load_or_init_in_workspace(ws.root.join(name), Some(ws.id))
+        })
+    }
+    /// Verify every registered object
+    /// Opens each packfile, checks the workspace ID in its
+    /// superblock and the checksum of its data.
+    pub fn verify(&self) -> PackResult<VerifyReport> {
+        let mut report = VerifyReport::default();
+        let mut files = vec![self.root.join(REGISTRY_FILE)];
+        for object in self.objects() {
+            match self.object_files(object) {
+                Ok(mut object_files) => files.append(&mut object_files),
+                Err(err) => {
+                    report.errors.push((self.root.join(&object.name), err))
+                }
+            }
+        }
+        for path in files {
+            report.checked += 1;
+            if let Err(err) = self.verify_file(&path) {
+                report.errors.push((path, err));
+            }
+        }
+        Ok(report)
+    }
+    /// Copy the whole workspace
+    /// into the given directory
+    pub fn backup(&self, to: PathBuf) -> PackResult<()> {
+        // Relative and absolute paths are compared
+        // after they are resolved
+        if resolve(&to)?.starts_with(resolve(&self.root)?) {
+            return Err(PackError::InternalError(
+                "Workspace backup target is inside the workspace".into(),
+            ));
+        }
+        copy_dir(&self.root, &to)
+    }
+    /// Collect storage statistics
+    /// of every registered object
+    pub fn stats(&self) -> PackResult<WorkspaceStats> {
+        let mut stats = WorkspaceStats::default();
+        for object in self.objects() {
+            let files = self.object_files(object)?;
+            let mut bytes = 0;
+            for file in &files {
+                bytes += std::fs::metadata(file)?.len();
+            }
+            stats.files += files.len();
+            stats.bytes += bytes;
+            stats.objects.push(ObjectStats {
+                name: object.name.clone(),
+                kind: object.kind,
+                files: files.len(),
+                bytes,
+            });
+        }
+        Ok(stats)
+    }
+    // Register object name if it is not registered
+    // yet, and its load succeeds
+    // In read-only mode, it must be registered
+    fn register<F, R>(
+        &mut self,
+        name: &str,
+        kind: ObjectKind,
+        load: F,
+    ) -> PackResult<R>
+    where
+        F: FnOnce(&Self) -> PackResult<R>,
+    {
+        let valid = !name.is_empty()
+            && Path::new(name)
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)))
+            && !name.starts_with('.');
+        if !valid {
+            return Err(PackError::InternalError(format!(
+                "Invalid workspace object name: {}",
+                name
+            )));
+        }
+        match self.objects().iter().find(|o| o.name == name) {
+            Some(object) if object.kind == kind => load(self),
+            Some(object) => Err(PackError::InternalError(format!(
+                "Workspace object {} is already registered as {:?}",
+                name, object.kind
+            ))),
+            None if self.read_only => Err(PackError::ObjectNotFound),
+            None => {
+                let object = load(self)?;
+                self.registry.update(|r| {
+                    r.objects.push(WorkspaceObject {
+                        name: name.to_string(),
+                        kind,
+                    })
+                })?;
+                Ok(object)
+            }
+        }
+    }
+    // Packfile paths belonging
+    // to a registered object
+    fn object_files(
+        &self,
+        object: &WorkspaceObject,
+    ) -> PackResult<Vec<PathBuf>> {
+        let path = self.root.join(&object.name);
+        if !path.exists() {
+            return Err(PackError::PathNotFound);
+        }
+        match object.kind {
+            ObjectKind::File => Ok(vec![path]),
+            ObjectKind::Folder => {
+                let layout = layout::read_manifest(&path)?.layout;
+                let mut files = layout::member_files(&path, layout)?;
+                files.sort();
+                Ok(files)
+            }
+        }
+    }
+    // Check a single packfile
+    fn verify_file(&self, path: &Path) -> PackResult<()> {
+        let mut pack_file = fs::PackFile::open_read_only(path)?;
+        if pack_file.get_workspace_id() != Some(self.id) {
+            return Err(PackError::WorkspaceMismatch(
+                self.id,
+                pack_file.get_workspace_id(),
+            ));
+        }
+        pack_file.load_data()?;
+        Ok(())
+    }
+}
+
+// Absolute path with symlinks resolved
+// The path does not need to exist
+fn resolve(path: &Path) -> PackResult<PathBuf> {
+    let path = std::path::absolute(path)?;
+    let mut existing = path.as_path();
+    let mut rest = Vec::new();
+    while !existing.exists() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name);
+                existing = parent;
+            }
+            _ => break,
+        }
+    }
+    let mut resolved = existing.canonicalize()?;
+    resolved.extend(rest.iter().rev());
+    Ok(resolved)
+}
+
+// Copy directory recursively
+fn copy_dir(from: &Path, to: &Path) -> PackResult<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,286 @@
+//! Write-behind saver
+//!
+//! Opt-in background saving for Pack<T>. Saves are serialized
+//! in the caller thread, then handed over to a worker thread that
+//! merges the requests per path, and only writes the last one when
+//! the debounce delay (or the max delay) has elapsed.
+
+use crate::*;
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// SaveError
+/// Error of a background save,
+/// delivered through the error channel
+#[derive(Debug)]
+pub struct SaveError {
+    pub path: PathBuf,
+    pub error: PackError,
+}
+
+// Worker commands
+enum Command {
+    Save {
+        path: PathBuf,
+        bytes: Vec<u8>,
+        workspace_id: Option<u64>,
+        delay: Duration,
+        max_delay: Duration,
+    },
+    // Write pending data for path (or every path)
+    // then send ack
+    Flush(Option<PathBuf>, Sender<()>),
+    Shutdown,
+}
+
+// Pending save of a single path
+struct Pending {
+    bytes: Vec<u8>,
+    workspace_id: Option<u64>,
+    first: Instant,
+    last: Instant,
+    delay: Duration,
+    max_delay: Duration,
+}
+
+impl Pending {
+    // When to write the pending data
+    fn deadline(&self) -> Instant {
+        std::cmp::min(self.last + self.delay, self.first + self.max_delay)
+    }
+}
+
+/// WriteBehind
+/// Owns the background worker thread.
+/// When dropped, it flushes every pending save
+/// and waits for the worker to finish.
+///
+/// ```rust,no_run
+/// use packman::write_behind::WriteBehind;
+/// use packman::*;
+/// # use std::path::PathBuf;
+/// use std::time::Duration;
+/// let (writer, errors) = WriteBehind::start();
+/// let mut counter: Pack<u32> =
+///     Pack::load_or_init(PathBuf::from("data"), "counter")?;
+/// counter.enable_write_behind(
+///     &writer.handle(),
+///     Duration::from_millis(50),
+///     Duration::from_secs(1),
+/// );
+/// for _ in 0..1000 {
+///     *counter.as_mut() += 1;
+/// }
+/// writer.flush_all()?;
+/// # Ok::<(), PackError>(())
+/// ```
+pub struct WriteBehind {
+    handle: WriteBehindHandle,
+    worker: Option<JoinHandle<()>>,
+}
+
+/// WriteBehindHandle
+/// Cloneable handle of a WriteBehind worker
+/// Packs use it to send their save requests.
+#[derive(Clone)]
+pub struct WriteBehindHandle {
+    sender: Sender<Command>,
+}
+
+impl WriteBehind {
+    /// Start a new worker thread
+    /// Returns the WriteBehind and the receiver
+    /// of the background save errors.
+    pub fn start() -> (WriteBehind, Receiver<SaveError>) {
+        let (sender, receiver) = channel();
+        let (error_sender, error_receiver) = channel();
+        let worker =
+            std::thread::spawn(move || run_worker(receiver, error_sender));
+        (
+            WriteBehind {
+                handle: WriteBehindHandle { sender },
+                worker: Some(worker),
+            },
+            error_receiver,
+        )
+    }
+    /// Returns a handle to this worker
+    pub fn handle(&self) -> WriteBehindHandle {
+        self.handle.clone()
+    }
+    /// Barrier: write every pending save
+    /// and wait until they are done
+    pub fn flush_all(&self) -> PackResult<()> {
+        self.handle.flush_all()
+    }
+    /// Flush every pending save
+    /// and stop the worker
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+    fn stop(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            let _ = self.handle.sender.send(Command::Shutdown);
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for WriteBehind {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl WriteBehindHandle {
+    /// Barrier: write the pending save
+    /// of path, and wait until it is done
+    pub fn flush(&self, path: &Path) -> PackResult<()> {
+        self.barrier(Some(path.to_path_buf()))
+    }
+    /// Barrier: write every pending save
+    /// and wait until they are done
+    pub fn flush_all(&self) -> PackResult<()> {
+        self.barrier(None)
+    }
+    // Queue save request
+    // If the worker is stopped, then returns the request bytes
+    pub(crate) fn save(
+        &self,
+        path: &Path,
+        bytes: Vec<u8>,
+        workspace_id: Option<u64>,
+        delay: Duration,
+        max_delay: Duration,
+    ) -> Result<(), Vec<u8>> {
+        self.sender
+            .send(Command::Save {
+                path: path.to_path_buf(),
+                bytes,
+                workspace_id,
+                delay,
+                max_delay,
+            })
+            .map_err(|err| match err.0 {
+                Command::Save { bytes, .. } => bytes,
+                _ => Vec::new(),
+            })
+    }
+    fn barrier(&self, path: Option<PathBuf>) -> PackResult<()> {
+        let (ack_sender, ack_receiver) = channel();
+        self.sender
+            .send(Command::Flush(path, ack_sender))
+            .map_err(|_| worker_stopped())?;
+        ack_receiver.recv().map_err(|_| worker_stopped())
+    }
+}
+
+fn worker_stopped() -> PackError {
+    PackError::InternalError("Write-behind worker is stopped".into())
+}
+
+// Worker thread main loop
+fn run_worker(receiver: Receiver<Command>, errors: Sender<SaveError>) {
+    let mut pending: HashMap<PathBuf, Pending> = HashMap::new();
+    loop {
+        let next_deadline = pending.values().map(|p| p.deadline()).min();
+        let command = match next_deadline {
+            Some(deadline) => {
+                let timeout =
+                    deadline.saturating_duration_since(Instant::now());
+                match receiver.recv_timeout(timeout) {
+                    Ok(command) => Some(command),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        Some(Command::Shutdown)
+                    }
+                }
+            }
+            None => Some(receiver.recv().unwrap_or(Command::Shutdown)),
+        };
+        match command {
+            Some(Command::Save {
+                path,
+                bytes,
+                workspace_id,
+                delay,
+                max_delay,
+            }) => {
+                let now = Instant::now();
+                match pending.get_mut(&path) {
+                    // Merge with the pending request
+                    // only the last data is going to be saved
+                    Some(p) => {
+                        p.bytes = bytes;
+                        p.workspace_id = workspace_id;
+                        p.last = now;
+                        p.delay = delay;
+                        p.max_delay = max_delay;
+                    }
+                    None => {
+                        pending.insert(
+                            path,
+                            Pending {
+                                bytes,
+                                workspace_id,
+                                first: now,
+                                last: now,
+                                delay,
+                                max_delay,
+                            },
+                        );
+                    }
+                }
+            }
+            Some(Command::Flush(Some(path), ack)) => {
+                if let Some(p) = pending.remove(&path) {
+                    write_pending(path, p, &errors);
+                }
+                let _ = ack.send(());
+            }
+            Some(Command::Flush(None, ack)) => {
+                write_all(&mut pending, &errors);
+                let _ = ack.send(());
+            }
+            Some(Command::Shutdown) => {
+                write_all(&mut pending, &errors);
+                return;
+            }
+            // Timeout, write every due request
+            None => {
+                let now = Instant::now();
+                let due: Vec<PathBuf> = pending
+                    .iter()
+                    .filter(|(_, p)| p.deadline() <= now)
+                    .map(|(path, _)| path.clone())
+                    .collect();
+                for path in due {
+                    if let Some(p) = pending.remove(&path) {
+                        write_pending(path, p, &errors);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn write_all(
+    pending: &mut HashMap<PathBuf, Pending>,
+    errors: &Sender<SaveError>,
+) {
+    for (path, p) in pending.drain() {
+        write_pending(path, p, errors);
+    }
+}
+
+fn write_pending(path: PathBuf, pending: Pending, errors: &Sender<SaveError>) {
+    if let Err(error) =
+        save_data_object(&path, &pending.bytes, pending.workspace_id)
+    {
+        // Receiver could be dropped,
+        // then we do not care about the error
+        let _ = errors.send(SaveError { path, error });
+    }
+}
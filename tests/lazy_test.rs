@@ -0,0 +1,58 @@
+use packman::lazy::*;
+use packman::workspace::*;
+use packman::*;
+use std::path::PathBuf;
+
+#[test]
+fn test_lazy_pack_load_evict() {
+  let _ = std::fs::remove_dir_all("data/lazy_test_pack");
+  let mut counter: Pack<u32> =
+    Pack::load_or_init(PathBuf::from("data/lazy_test_pack"), "counter")
+      .unwrap();
+  *counter.as_mut() = 41;
+  let mut lazy: LazyPack<u32> =
+    LazyPack::open(PathBuf::from("data/lazy_test_pack/counter")).unwrap();
+  assert!(!lazy.is_loaded());
+  assert_eq!(lazy.version(), counter.version());
+  assert_eq!(*lazy, 41);
+  assert!(lazy.is_loaded());
+  lazy.update(|c| *c += 1).unwrap();
+  assert_eq!(lazy.evict().unwrap(), true);
+  assert!(!lazy.is_loaded());
+  assert_eq!(lazy.version(), counter.version() + 1);
+  assert_eq!(lazy.evict().unwrap(), false);
+  assert_eq!(*lazy.get().unwrap(), 42);
+
+  // Header is checked on open
+  std::fs::write("data/lazy_test_pack/not_packfile", "42").unwrap();
+  assert!(LazyPack::<u32>::open(PathBuf::from(
+    "data/lazy_test_pack/not_packfile"
+  ))
+  .is_err());
+  // Data is only checked on first access
+  std::fs::remove_file("data/lazy_test_pack/not_packfile").unwrap();
+  let mut text: Pack<String> =
+    Pack::load_or_init(PathBuf::from("data/lazy_test_pack"), "text").unwrap();
+  *text.as_mut() = "not a number".to_string();
+  let lazy: LazyPack<u32> =
+    LazyPack::open(PathBuf::from("data/lazy_test_pack/text")).unwrap();
+  assert!(lazy.get().is_err());
+}
+
+#[test]
+fn test_workspace_lazy_file() {
+  let root = PathBuf::from("data/lazy_test_workspace");
+  let _ = std::fs::remove_dir_all(&root);
+  let mut ws = Workspace::load_or_init(root.clone(), 3).unwrap();
+  let mut stock: LazyPack<u32> = ws.lazy_file_from("stock").unwrap();
+  assert!(stock.is_loaded());
+  stock.update(|s| *s = 10).unwrap();
+  drop(ws);
+
+  let mut ws = Workspace::load_or_init(root, 3).unwrap();
+  let stock: LazyPack<u32> = ws.lazy_file_from("stock").unwrap();
+  assert!(!stock.is_loaded());
+  assert_eq!(*stock, 10);
+  assert_eq!(stock.pack().unwrap().is_read_only(), false);
+  assert!(ws.verify().unwrap().is_ok());
+}
@@ -0,0 +1,136 @@
+use packman::load::LoadOptions;
+use packman::*;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Ticket {
+  id: u32,
+  title: String,
+}
+
+impl VecPackMember for Ticket {
+  type Out = u32;
+  fn get_id(&self) -> &u32 {
+    &self.id
+  }
+}
+
+fn create_tickets(path: &str, count: u32) -> PathBuf {
+  let path = PathBuf::from(path);
+  let _ = std::fs::remove_dir_all(&path);
+  let mut tickets: VecPack<Ticket> =
+    VecPack::load_or_init(path.clone()).unwrap();
+  for id in 0..count {
+    tickets
+      .insert(Ticket {
+        id,
+        title: format!("ticket_{}", id),
+      })
+      .unwrap();
+  }
+  path
+}
+
+#[test]
+fn test_load_parallel() {
+  let path = create_tickets("data/load_test_parallel", 50);
+  let options = LoadOptions::default().threads(4).batch_size(7);
+  let (tickets, report) =
+    VecPack::<Ticket>::load_parallel(path.clone(), options).unwrap();
+  assert_eq!(tickets.len(), 50);
+  assert_eq!(report.threads, 4);
+  assert_eq!(report.files.len(), 50);
+  assert_eq!(report.slowest(3).len(), 3);
+  // Filename order, the same in every run
+  let mut names = (0..50).map(|id| id.to_string()).collect::<Vec<_>>();
+  names.sort();
+  let ids = tickets.iter().map(|t| t.id.to_string()).collect::<Vec<_>>();
+  assert_eq!(ids, names);
+  assert!(report
+    .files
+    .iter()
+    .zip(names.iter())
+    .all(|(file, name)| file.path == path.join(name) && file.bytes > 0));
+  assert_eq!(tickets.find_id(&42).unwrap().title, "ticket_42");
+}
+
+#[test]
+fn test_load_parallel_error() {
+  let path = create_tickets("data/load_test_parallel_error", 10);
+  std::fs::write(path.join("5"), "not a packfile").unwrap();
+  let res = VecPack::<Ticket>::load_parallel(path, LoadOptions::default());
+  assert!(match res {
+    Err(PackError::MemberLoadError(file, _)) => file.ends_with("5"),
+    _ => false,
+  });
+  // Empty directory
+  let path = PathBuf::from("data/load_test_parallel_empty");
+  let _ = std::fs::remove_dir_all(&path);
+  let (tickets, report) =
+    VecPack::<Ticket>::load_parallel(path, LoadOptions::default()).unwrap();
+  assert!(tickets.is_empty());
+  assert!(report.files.is_empty());
+}
+
+#[test]
+fn test_load_lenient_quarantine() {
+  let path = create_tickets("data/load_test_lenient", 10);
+  std::fs::write(path.join("5"), "not a packfile").unwrap();
+  std::fs::write(path.join("notes.txt~"), "editor backup").unwrap();
+  std::fs::copy(path.join("3"), path.join("33")).unwrap();
+  // Errors instead of panics
+  assert!(VecPack::<Ticket>::load_or_init(path.clone()).is_err());
+  assert!(match VecPack::<Ticket>::load_or_init(path.join("3")) {
+    Err(PackError::NotADirectory(_)) => true,
+    _ => false,
+  });
+
+  let options = LoadOptions::default().lenient(true);
+  let (tickets, report) =
+    VecPack::<Ticket>::load_parallel(path.clone(), options).unwrap();
+  assert_eq!(tickets.len(), 9);
+  assert_eq!(report.files.len(), 9);
+  let mut failed = report
+    .failures
+    .iter()
+    .map(|f| f.path.file_name().unwrap().to_str().unwrap())
+    .collect::<Vec<_>>();
+  failed.sort();
+  assert_eq!(failed, vec!["33", "5", "notes.txt~"]);
+  // A valid member with a conflicting ID stays in place
+  let mismatch = report.failures.iter().find(|f| f.path.ends_with("33"));
+  let mismatch = mismatch.unwrap();
+  assert!(matches!(mismatch.error, PackError::IDMismatch(_, _)));
+  assert!(mismatch.quarantined.is_none());
+  assert!(path.join("33").is_file());
+  assert!(report
+    .failures
+    .iter()
+    .filter(|f| !f.path.ends_with("33"))
+    .all(|f| !f.path.exists() && f.quarantined.as_ref().unwrap().is_file()));
+  assert!(path.join(layout::QUARANTINE_DIR).join("5").is_file());
+  drop(tickets);
+
+  // Quarantined files are not loaded again
+  let (tickets, failures) =
+    VecPack::<Ticket>::load_or_init_lenient(path.clone()).unwrap();
+  assert_eq!(tickets.len(), 9);
+  assert_eq!(failures.len(), 1);
+  assert!(
+    failures[0].path.ends_with("33") && failures[0].quarantined.is_none()
+  );
+  drop(tickets);
+
+  // Lenient mode of load_or_init
+  std::fs::remove_file(path.join("33")).unwrap();
+  std::fs::write(path.join("7"), "not a packfile").unwrap();
+  assert!(VecPack::<Ticket>::load_or_init(path.clone()).is_err());
+  let (tickets, failures) =
+    VecPack::<Ticket>::load_or_init_lenient(path.clone()).unwrap();
+  assert_eq!(tickets.len(), 8);
+  assert_eq!(failures.len(), 1);
+  assert!(failures[0].path.ends_with("7"));
+  assert!(path.join(layout::QUARANTINE_DIR).join("7").is_file());
+  assert!(!path.join("7").exists());
+}
@@ -0,0 +1,106 @@
+use packman::observer::*;
+use packman::*;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[test]
+fn test_pack_subscribe() {
+  let _ = std::fs::remove_dir_all("data/observer_test_pack");
+  let mut counter: Pack<u32> =
+    Pack::load_or_init(PathBuf::from("data/observer_test_pack"), "counter")
+      .unwrap();
+  let calls = Arc::new(AtomicUsize::new(0));
+  let calls_clone = calls.clone();
+  let subscription = counter.subscribe(false, move |event| {
+    assert_eq!(event.kind, ChangeKind::Saved);
+    assert!(event.new.is_none());
+    calls_clone.fetch_add(1, Ordering::SeqCst);
+  });
+  let (values, events) = counter.subscribe_channel(true);
+  *counter.as_mut() = 1;
+  counter.update(|c| *c += 1).unwrap();
+  // Read only access is not a change
+  let _ = *counter.as_mut();
+  assert_eq!(calls.load(Ordering::SeqCst), 2);
+  let event = events.try_recv().unwrap();
+  assert_eq!(event.id, "counter");
+  assert_eq!((event.old, event.new), (Some(0), Some(1)));
+  let event = events.try_recv().unwrap();
+  assert_eq!(event.version, counter.version());
+  assert_eq!((event.old, event.new), (Some(1), Some(2)));
+  assert!(events.try_recv().is_err());
+
+  drop(subscription);
+  values.unsubscribe();
+  *counter.as_mut() = 3;
+  assert_eq!(calls.load(Ordering::SeqCst), 2);
+  assert!(events.try_recv().is_err());
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq)]
+struct Order {
+  id: u32,
+  total: u32,
+}
+
+impl VecPackMember for Order {
+  type Out = u32;
+  fn get_id(&self) -> &u32 {
+    &self.id
+  }
+}
+
+#[test]
+fn test_vecpack_subscribe() {
+  let _ = std::fs::remove_dir_all("data/observer_test_vecpack");
+  let mut orders: VecPack<Order> =
+    VecPack::load_or_init(PathBuf::from("data/observer_test_vecpack")).unwrap();
+  let (_subscription, events) = orders.subscribe_channel(false);
+  orders.insert(Order { id: 1, total: 10 }).unwrap();
+  orders.find_id_mut(&1).unwrap().as_mut().total = 20;
+  orders.remove_pack(&1).unwrap();
+  let kinds: Vec<(ChangeKind, String)> =
+    events.try_iter().map(|e| (e.kind, e.id)).collect();
+  assert_eq!(
+    kinds,
+    vec![
+      (ChangeKind::Inserted, "1".to_string()),
+      (ChangeKind::Saved, "1".to_string()),
+      (ChangeKind::Removed, "1".to_string()),
+    ]
+  );
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Note {
+  id: String,
+}
+
+impl VecPackMember for Note {
+  type Out = String;
+  fn get_id(&self) -> &String {
+    &self.id
+  }
+}
+
+#[test]
+fn test_vecpack_event_id() {
+  let _ = std::fs::remove_dir_all("data/observer_test_event_id");
+  let mut notes: VecPack<Note> =
+    VecPack::load_or_init(PathBuf::from("data/observer_test_event_id"))
+      .unwrap();
+  let (_subscription, events) = notes.subscribe_channel(false);
+  // Filename is percent-encoded, the event ID is not
+  notes
+    .insert(Note {
+      id: "a/b".to_string(),
+    })
+    .unwrap();
+  // Event ID is the new ID after an ID change
+  notes.find_id_mut(&"a/b".to_string()).unwrap().as_mut().id =
+    "c:d".to_string();
+  let ids: Vec<String> = events.try_iter().map(|e| e.id).collect();
+  assert_eq!(ids, vec!["a/b".to_string(), "c:d".to_string()]);
+}
@@ -240,3 +240,492 @@ fn test_changed_field_order_updated_enum() {
         _ => false,
     });
 }
+
+#[test]
+fn test_guard_read_skips_save() {
+    let path = PathBuf::from("data/pack_test/meaning_of_life_dirty");
+    let _ = std::fs::remove_file(&path);
+    let mut meaning_of_life: Pack<i32> = Pack::load_or_init(
+        PathBuf::from("data/pack_test"),
+        "meaning_of_life_dirty",
+    )
+    .unwrap();
+    let version = fs::PackFile::open(&path).unwrap().metadata().file_version;
+    // Read only access through guard
+    let value = *meaning_of_life.as_mut();
+    assert_eq!(value, 0);
+    assert_eq!(meaning_of_life.save_stats().skipped, 1);
+    assert_eq!(meaning_of_life.save_stats().saved, 0);
+    assert_eq!(
+        fs::PackFile::open(&path).unwrap().metadata().file_version,
+        version
+    );
+    // Mutable access
+    *meaning_of_life.as_mut() = 42;
+    assert_eq!(meaning_of_life.save_stats().saved, 1);
+    assert_eq!(meaning_of_life.is_dirty(), false);
+    assert_eq!(
+        fs::PackFile::open(&path).unwrap().metadata().file_version,
+        version + 1
+    );
+}
+
+#[test]
+fn test_compare_on_save() {
+    let mut meaning_of_life: Pack<i32> = Pack::load_or_init(
+        PathBuf::from("data/pack_test"),
+        "meaning_of_life_compare",
+    )
+    .unwrap();
+    meaning_of_life.set_compare_on_save(true);
+    *meaning_of_life.as_mut() = 42;
+    let stats = meaning_of_life.save_stats();
+    // Same value again
+    *meaning_of_life.as_mut() = 42;
+    meaning_of_life.update(|i| *i = 42).unwrap();
+    assert_eq!(meaning_of_life.save_stats().saved, stats.saved);
+    assert_eq!(meaning_of_life.save_stats().skipped, stats.skipped + 2);
+    meaning_of_life.update(|i| *i = 43).unwrap();
+    assert_eq!(meaning_of_life.save_stats().saved, stats.saved + 1);
+}
+
+// Replace packfile with a directory,
+// so the next save fails
+fn break_packfile(path: &str) {
+    std::fs::remove_file(path).unwrap();
+    std::fs::create_dir(path).unwrap();
+}
+
+#[test]
+fn test_guard_commit_abort() {
+    let mut meaning_of_life: Pack<i32> = Pack::load_or_init(
+        PathBuf::from("data/pack_test"),
+        "meaning_of_life_commit",
+    )
+    .unwrap();
+    let mut guard = meaning_of_life.as_mut();
+    *guard = 42;
+    guard.commit().unwrap();
+    let mut guard = meaning_of_life.as_mut();
+    *guard = 17;
+    guard.abort();
+    assert_eq!(*meaning_of_life, 42);
+    let meaning_of_life: Pack<i32> = Pack::load_or_init(
+        PathBuf::from("data/pack_test"),
+        "meaning_of_life_commit",
+    )
+    .unwrap();
+    assert_eq!(*meaning_of_life, 42);
+}
+
+#[test]
+fn test_guard_drop_error_rollback() {
+    let _ = std::fs::remove_dir_all("data/pack_test_drop_error");
+    let mut meaning_of_life: Pack<i32> = Pack::load_or_init(
+        PathBuf::from("data/pack_test_drop_error"),
+        "meaning_of_life",
+    )
+    .unwrap();
+    *meaning_of_life.as_mut() = 42;
+    break_packfile("data/pack_test_drop_error/meaning_of_life");
+    // Implicit save fails, data is rolled back
+    *meaning_of_life.as_mut() = 17;
+    assert_eq!(*meaning_of_life, 42);
+    assert_eq!(meaning_of_life.is_dirty(), false);
+    // Explicit commit returns the error
+    let mut guard = meaning_of_life.as_mut();
+    *guard = 17;
+    assert!(guard.commit().is_err());
+    assert_eq!(*meaning_of_life, 42);
+}
+
+#[test]
+fn test_guard_drop_error_retry_queue() {
+    let _ = std::fs::remove_dir_all("data/pack_test_retry_queue");
+    let queue = RetryQueue::new();
+    let mut meaning_of_life: Pack<i32> = Pack::load_or_init(
+        PathBuf::from("data/pack_test_retry_queue"),
+        "meaning_of_life",
+    )
+    .unwrap();
+    meaning_of_life
+        .set_drop_error_handler(DropErrorHandler::Retry(queue.clone()));
+    break_packfile("data/pack_test_retry_queue/meaning_of_life");
+    *meaning_of_life.as_mut() = 42;
+    assert_eq!(*meaning_of_life, 0);
+    assert_eq!(queue.len(), 1);
+    assert_eq!(queue.retry(), 0);
+    assert_eq!(queue.len(), 1);
+    std::fs::remove_dir("data/pack_test_retry_queue/meaning_of_life").unwrap();
+    assert_eq!(queue.retry(), 1);
+    assert!(queue.is_empty());
+    let meaning_of_life: Pack<i32> = Pack::load_or_init(
+        PathBuf::from("data/pack_test_retry_queue"),
+        "meaning_of_life",
+    )
+    .unwrap();
+    assert_eq!(*meaning_of_life, 42);
+}
+
+#[test]
+fn test_retry_queue_version_check() {
+    let _ = std::fs::remove_dir_all("data/pack_test_retry_version");
+    let queue = RetryQueue::new();
+    let mut meaning_of_life: Pack<i32> = Pack::load_or_init(
+        PathBuf::from("data/pack_test_retry_version"),
+        "meaning_of_life",
+    )
+    .unwrap();
+    meaning_of_life
+        .set_drop_error_handler(DropErrorHandler::Retry(queue.clone()));
+    break_packfile("data/pack_test_retry_version/meaning_of_life");
+    *meaning_of_life.as_mut() = 42;
+    assert_eq!(queue.len(), 1);
+    // Newer save since the failure
+    std::fs::remove_dir("data/pack_test_retry_version/meaning_of_life")
+        .unwrap();
+    let mut other: Pack<i32> = Pack::load_or_init(
+        PathBuf::from("data/pack_test_retry_version"),
+        "meaning_of_life",
+    )
+    .unwrap();
+    other.update(|m| *m = 7).unwrap();
+    other.update(|m| *m += 1).unwrap();
+    assert_eq!(queue.retry(), 0);
+    let failed = queue.take();
+    assert!(matches!(failed[0].error, PackError::VersionConflict(_, _)));
+    other.reload().unwrap();
+    assert_eq!(*other, 8);
+
+    // Only IO errors are queued
+    let mut read_only: Pack<i32> = Pack::load_read_only(PathBuf::from(
+        "data/pack_test_retry_version/meaning_of_life",
+    ))
+    .unwrap();
+    read_only.set_drop_error_handler(DropErrorHandler::Retry(queue.clone()));
+    *read_only.as_mut() = 1;
+    assert!(queue.is_empty());
+}
+
+#[test]
+#[should_panic]
+fn test_guard_drop_error_panic() {
+    let _ = std::fs::remove_dir_all("data/pack_test_drop_panic");
+    let mut meaning_of_life: Pack<i32> = Pack::load_or_init(
+        PathBuf::from("data/pack_test_drop_panic"),
+        "meaning_of_life",
+    )
+    .unwrap();
+    meaning_of_life.set_drop_error_handler(DropErrorHandler::Panic);
+    break_packfile("data/pack_test_drop_panic/meaning_of_life");
+    *meaning_of_life.as_mut() = 42;
+}
+
+#[test]
+fn test_update_if_version() {
+    let _ = std::fs::remove_dir_all("data/pack_test_version");
+    let mut first: Pack<i32> = Pack::load_or_init(
+        PathBuf::from("data/pack_test_version"),
+        "meaning_of_life",
+    )
+    .unwrap();
+    let mut second: Pack<i32> = Pack::load_or_init(
+        PathBuf::from("data/pack_test_version"),
+        "meaning_of_life",
+    )
+    .unwrap();
+    let version = first.version();
+    assert_eq!(second.version(), version);
+    first.update_if_version(version, |i| *i = 42).unwrap();
+    assert_eq!(first.version(), version + 1);
+    // Second is loaded before the first save
+    let res = second.update_if_version(version, |i| *i = 17);
+    assert!(match res {
+        Err(PackError::VersionConflict(expected, found)) =>
+            expected == version && found == version + 1,
+        _ => false,
+    });
+    assert_eq!(*second, 0);
+    let loaded: Pack<i32> = Pack::load_or_init(
+        PathBuf::from("data/pack_test_version"),
+        "meaning_of_life",
+    )
+    .unwrap();
+    assert_eq!(*loaded, 42);
+    assert_eq!(loaded.version(), version + 1);
+}
+
+#[test]
+fn test_reload_is_stale() {
+    let _ = std::fs::remove_dir_all("data/pack_test_reload");
+    let mut first: Pack<i32> = Pack::load_or_init(
+        PathBuf::from("data/pack_test_reload"),
+        "meaning_of_life",
+    )
+    .unwrap();
+    let mut second: Pack<i32> = Pack::load_or_init(
+        PathBuf::from("data/pack_test_reload"),
+        "meaning_of_life",
+    )
+    .unwrap();
+    assert!(!second.is_stale().unwrap());
+    assert!(!second.reload().unwrap());
+    *first.as_mut() = 42;
+    assert!(!first.is_stale().unwrap());
+    assert!(second.is_stale().unwrap());
+    assert!(second.reload().unwrap());
+    assert_eq!(*second, 42);
+    assert!(!second.is_stale().unwrap());
+}
+
+#[test]
+fn test_try_update() {
+    let _ = std::fs::remove_dir_all("data/pack_test_try_update");
+    let mut car: Pack<Car> =
+        Pack::load_or_init(PathBuf::from("data/pack_test_try_update"), "car")
+            .unwrap();
+    let seats = car
+        .try_update(|c| -> Result<u32, String> {
+            c.number_of_seats = 4;
+            Ok(c.number_of_seats)
+        })
+        .unwrap();
+    assert_eq!(seats, 4);
+    // Closure fails after a partial mutation
+    let res = car.try_update(|c| {
+        c.number_of_seats = 0;
+        if c.number_of_seats == 0 {
+            return Err("Car must have seats".to_string());
+        }
+        Ok(())
+    });
+    assert!(match res {
+        Err(UpdateError::User(msg)) => msg == "Car must have seats",
+        _ => false,
+    });
+    assert_eq!(car.number_of_seats, 4);
+    let car: Pack<Car> =
+        Pack::load_or_init(PathBuf::from("data/pack_test_try_update"), "car")
+            .unwrap();
+    assert_eq!(car.number_of_seats, 4);
+}
+
+#[test]
+fn test_previous_rollback() {
+    let _ = std::fs::remove_dir_all("data/pack_test_rollback");
+    let mut meaning_of_life: Pack<i32> = Pack::load_or_init(
+        PathBuf::from("data/pack_test_rollback"),
+        "meaning_of_life",
+    )
+    .unwrap();
+    assert!(match meaning_of_life.previous() {
+        Err(PackError::NoBackup) => true,
+        _ => false,
+    });
+    *meaning_of_life.as_mut() = 42;
+    *meaning_of_life.as_mut() = 17;
+    assert_eq!(meaning_of_life.previous().unwrap(), 42);
+    let version = meaning_of_life.version();
+    meaning_of_life.rollback().unwrap();
+    assert_eq!(*meaning_of_life, 42);
+    assert_eq!(meaning_of_life.version(), version + 1);
+    assert_eq!(meaning_of_life.previous().unwrap(), 17);
+    let loaded: Pack<i32> = Pack::load_or_init(
+        PathBuf::from("data/pack_test_rollback"),
+        "meaning_of_life",
+    )
+    .unwrap();
+    assert_eq!(*loaded, 42);
+}
+
+#[test]
+fn test_load_read_only() {
+    let _ = std::fs::remove_dir_all("data/pack_test_read_only");
+    let mut meaning_of_life: Pack<i32> = Pack::load_or_init(
+        PathBuf::from("data/pack_test_read_only"),
+        "meaning_of_life",
+    )
+    .unwrap();
+    *meaning_of_life.as_mut() = 42;
+    let path = PathBuf::from("data/pack_test_read_only/meaning_of_life");
+    let mut read_only: Pack<i32> = Pack::load_read_only(path.clone()).unwrap();
+    assert!(read_only.is_read_only());
+    let version = read_only.version();
+    assert!(match read_only.update(|i| *i = 17) {
+        Err(PackError::ReadOnly) => true,
+        _ => false,
+    });
+    // Implicit save fails, change is rolled back
+    *read_only.as_mut() = 17;
+    assert_eq!(*read_only, 42);
+    assert!(read_only.save().is_err());
+    let mut pack_file = fs::PackFile::open_read_only(&path).unwrap();
+    assert!(pack_file.write_data(b"17").is_err());
+    assert_eq!(pack_file.metadata().file_version, version);
+}
+
+#[test]
+fn test_delete_move_to() {
+    let _ = std::fs::remove_dir_all("data/pack_test_move");
+    let mut meaning_of_life: Pack<i32> = Pack::load_or_init(
+        PathBuf::from("data/pack_test_move/a"),
+        "meaning_of_life",
+    )
+    .unwrap();
+    *meaning_of_life.as_mut() = 42;
+    let other: Pack<i32> =
+        Pack::load_or_init(PathBuf::from("data/pack_test_move/b"), "other")
+            .unwrap();
+    assert!(match meaning_of_life
+        .move_to(PathBuf::from("data/pack_test_move/b/other"))
+    {
+        Err(PackError::IDTaken) => true,
+        _ => false,
+    });
+    meaning_of_life
+        .move_to(PathBuf::from("data/pack_test_move/b/meaning_of_life"))
+        .unwrap();
+    assert!(!PathBuf::from("data/pack_test_move/a/meaning_of_life").exists());
+    *meaning_of_life.as_mut() += 1;
+    let loaded: Pack<i32> = Pack::load_or_init(
+        PathBuf::from("data/pack_test_move/b"),
+        "meaning_of_life",
+    )
+    .unwrap();
+    assert_eq!(*loaded, 43);
+
+    assert_eq!(other.delete().unwrap(), 0);
+    assert!(!PathBuf::from("data/pack_test_move/b/other").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_move_to_hard_link() {
+    let path = PathBuf::from("data/pack_test_move_link");
+    let _ = std::fs::remove_dir_all(&path);
+    let mut meaning_of_life: Pack<i32> =
+        Pack::load_or_init(path.join("a"), "meaning_of_life").unwrap();
+    *meaning_of_life.as_mut() = 42;
+    // Both paths link the packfile, as if a move
+    // was interrupted between link and remove
+    let target = path.join("b").join("meaning_of_life");
+    std::fs::create_dir_all(path.join("b")).unwrap();
+    std::fs::hard_link(meaning_of_life.get_path(), &target).unwrap();
+    meaning_of_life.move_to(target.clone()).unwrap();
+    assert!(!path.join("a").join("meaning_of_life").exists());
+    assert_eq!(meaning_of_life.get_path(), target.as_path());
+    let loaded: Pack<i32> =
+        Pack::load_or_init(path.join("b"), "meaning_of_life").unwrap();
+    assert_eq!(*loaded, 42);
+    // A copy is not the same packfile
+    std::fs::copy(&target, path.join("a").join("meaning_of_life")).unwrap();
+    let mut copy: Pack<i32> =
+        Pack::load_or_init(path.join("a"), "meaning_of_life").unwrap();
+    assert!(matches!(copy.move_to(target), Err(PackError::IDTaken)));
+    assert!(copy.get_path().is_file());
+}
+
+#[test]
+fn test_open_packfile_replaced() {
+    let path = PathBuf::from("data/pack_test_open_replaced");
+    let _ = std::fs::remove_dir_all(&path);
+    let mut counter: Pack<u32> =
+        Pack::load_or_init(path.clone(), "counter").unwrap();
+    counter.update(|c| *c = 1).unwrap();
+    let mut other: Pack<u32> =
+        Pack::load_or_init(path.clone(), "other").unwrap();
+    for _ in 0..5 {
+        other.update(|c| *c += 10).unwrap();
+    }
+    // Replaced by an other packfile, e.g. a restored copy
+    std::fs::rename(path.join("other"), path.join("counter")).unwrap();
+    counter.update(|c| *c += 1).unwrap();
+    assert_eq!(counter.version(), other.version() + 1);
+    let loaded: Pack<u32> =
+        Pack::load_or_init(path.clone(), "counter").unwrap();
+    assert_eq!(*loaded, 2);
+    // Written by an other handle, timestamps
+    // of some filesystems are coarse
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    let mut writer = fs::PackFile::open(&path.join("counter")).unwrap();
+    writer.write_data(b"7").unwrap();
+    counter.update(|c| *c += 1).unwrap();
+    assert_eq!(counter.version(), writer.get_version() + 1);
+    let loaded: Pack<u32> = Pack::load_or_init(path, "counter").unwrap();
+    assert_eq!(*loaded, 3);
+}
+
+// No Default implementation
+#[derive(Serialize, Deserialize, Clone)]
+struct Settings {
+    currency: String,
+    vat: u32,
+}
+
+#[test]
+fn test_load_or_init_with() {
+    let _ = std::fs::remove_dir_all("data/pack_test_init_with");
+    let mut settings: Pack<Settings> = Pack::load_or_init_with(
+        PathBuf::from("data/pack_test_init_with"),
+        "settings",
+        || Settings {
+            currency: "HUF".to_string(),
+            vat: 27,
+        },
+    )
+    .unwrap();
+    assert_eq!(settings.currency, "HUF");
+    settings.update(|s| s.vat = 5).unwrap();
+    // Existing packfile is loaded, init is not called
+    let loaded: Pack<Settings> = Pack::load_or_init_with(
+        PathBuf::from("data/pack_test_init_with"),
+        "settings",
+        || panic!("init called for an existing packfile"),
+    )
+    .unwrap();
+    assert_eq!(loaded.vat, 5);
+}
+
+#[test]
+fn test_open_packfile_external_write() {
+    let _ = std::fs::remove_dir_all("data/pack_test_open_file");
+    let mut counter: Pack<u32> = Pack::load_or_init(
+        PathBuf::from("data/pack_test_open_file"),
+        "counter",
+    )
+    .unwrap();
+    for _ in 0..10 {
+        counter.update(|c| *c += 1).unwrap();
+    }
+    assert_eq!(counter.version(), 11);
+    let mut other: Pack<u32> = Pack::load_or_init(
+        PathBuf::from("data/pack_test_open_file"),
+        "counter",
+    )
+    .unwrap();
+    assert_eq!(*other, 10);
+    other.update(|c| *c += 5).unwrap();
+    other.update(|c| *c += 5).unwrap();
+    // Version check reads the inodes again
+    assert!(counter.update_if_version(11, |c| *c = 0).is_err());
+    assert_eq!(counter.reload().unwrap(), true);
+    counter.update(|c| *c += 1).unwrap();
+    // Replaced packfile is opened again
+    std::fs::remove_file("data/pack_test_open_file/counter").unwrap();
+    other.save().unwrap();
+    counter.update(|c| *c += 1).unwrap();
+    let loaded: Pack<u32> = Pack::load_or_init(
+        PathBuf::from("data/pack_test_open_file"),
+        "counter",
+    )
+    .unwrap();
+    assert_eq!(*loaded, 22);
+    // Every save gets a new version
+    let version = counter.version();
+    counter.update(|c| *c += 1).unwrap();
+    other.update(|c| *c += 1).unwrap();
+    counter.update(|c| *c += 1).unwrap();
+    assert_eq!(counter.version(), version + 3);
+    assert_eq!(other.version(), version + 2);
+    assert_eq!(other.is_stale().unwrap(), true);
+}
@@ -0,0 +1,167 @@
+use packman::index::IndexKind;
+use packman::query::Cursor;
+use packman::*;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Customer {
+  id: u32,
+  name: String,
+  status: String,
+  age: u32,
+}
+
+impl VecPackMember for Customer {
+  type Out = u32;
+  fn get_id(&self) -> &u32 {
+    &self.id
+  }
+}
+
+fn create_customers(path: &str) -> VecPack<Customer> {
+  let _ = std::fs::remove_dir_all(path);
+  let mut customers: VecPack<Customer> =
+    VecPack::load_or_init(PathBuf::from(path)).unwrap();
+  for id in 0..10 {
+    customers
+      .insert(Customer {
+        id,
+        name: format!("customer_{}", 9 - id),
+        status: if id % 2 == 0 { "active" } else { "inactive" }.to_string(),
+        age: 20 + id % 3,
+      })
+      .unwrap();
+  }
+  customers
+}
+
+fn ids(items: &[&Pack<Customer>]) -> Vec<u32> {
+  items.iter().map(|c| c.id).collect()
+}
+
+#[test]
+fn test_query_filter_sort() {
+  let mut customers = create_customers("data/query_test_filter");
+  let active = |c: &Customer| c.status.clone();
+  let res = customers
+    .query()
+    .index_eq("status", "active", active)
+    .sort_by_key(|c| c.name.clone())
+    .run()
+    .unwrap();
+  assert_eq!(ids(&res), vec![8, 6, 4, 2, 0]);
+  // Same result with the index
+  customers
+    .add_index("status", IndexKind::Multi, active)
+    .unwrap();
+  let res = customers
+    .query()
+    .index_eq("status", "active", active)
+    .sort_by_key(|c| c.name.clone())
+    .run()
+    .unwrap();
+  assert_eq!(ids(&res), vec![8, 6, 4, 2, 0]);
+  // Multi-key sort, offset and limit
+  let res = customers
+    .query()
+    .filter(|c| c.id > 1)
+    .sort_by_key_desc(|c| c.age)
+    .sort_by_key(|c| c.id)
+    .offset(1)
+    .limit(4)
+    .run()
+    .unwrap();
+  assert_eq!(ids(&res), vec![5, 8, 4, 7]);
+  assert_eq!(
+    customers.query().filter(|c| c.age == 20).count().unwrap(),
+    4
+  );
+}
+
+#[test]
+fn test_query_cursor_pagination() {
+  let mut customers = create_customers("data/query_test_cursor");
+  let page = customers.query().sort_by_key(|c| c.age).page(4).unwrap();
+  assert_eq!(ids(&page.items), vec![0, 3, 6, 9]);
+  let cursor = page.next.unwrap();
+  // Removing a member does not move the next page
+  customers.remove_pack(&1).unwrap();
+  let page = customers
+    .query()
+    .sort_by_key(|c| c.age)
+    .after(cursor.to_string().into())
+    .page(4)
+    .unwrap();
+  assert_eq!(ids(&page.items), vec![4, 7, 2, 5]);
+  let page = customers
+    .query()
+    .sort_by_key(|c| c.age)
+    .after(page.next.unwrap())
+    .page(4)
+    .unwrap();
+  assert_eq!(ids(&page.items), vec![8]);
+  assert!(page.next.is_none());
+  // Member of the cursor is removed
+  customers.remove_pack(&9).unwrap();
+  let res = customers
+    .query()
+    .sort_by_key(|c| c.age)
+    .after(cursor.clone())
+    .run()
+    .unwrap();
+  assert_eq!(ids(&res), vec![4, 7, 2, 5, 8]);
+  // Compare functions need the member of the cursor
+  let res = customers
+    .query()
+    .sort_by(|a, b| a.age.cmp(&b.age))
+    .after(cursor)
+    .run();
+  assert!(matches!(res, Err(PackError::ObjectNotFound)));
+  assert!(customers.query().after(Cursor::from("9")).run().is_err());
+}
+
+#[test]
+fn test_query_empty_page() {
+  let customers = create_customers("data/query_test_empty_page");
+  let page = customers.query().sort_by_key(|c| c.age).page(0).unwrap();
+  assert!(page.items.is_empty());
+  let page = customers
+    .query()
+    .sort_by_key(|c| c.age)
+    .after(page.next.unwrap())
+    .page(4)
+    .unwrap();
+  assert_eq!(ids(&page.items), vec![0, 3, 6, 9]);
+  let page = customers
+    .query()
+    .sort_by_key(|c| c.age)
+    .after(page.next.unwrap())
+    .page(0)
+    .unwrap();
+  assert!(page.items.is_empty());
+  let page = customers
+    .query()
+    .sort_by_key(|c| c.age)
+    .after(page.next.unwrap())
+    .page(4)
+    .unwrap();
+  assert_eq!(ids(&page.items), vec![1, 4, 7, 2]);
+  // No next cursor past the last result
+  let page = customers.query().filter(|_| false).page(0).unwrap();
+  assert!(page.next.is_none());
+}
+
+#[test]
+fn test_query_offset_after_cursor() {
+  let customers = create_customers("data/query_test_offset_cursor");
+  let query = || customers.query().sort_by_key(|c| c.age).offset(2);
+  let page = query().page(3).unwrap();
+  assert_eq!(ids(&page.items), vec![6, 9, 1]);
+  // The offset is not applied again on the next pages
+  let page = query().after(page.next.unwrap()).page(3).unwrap();
+  assert_eq!(ids(&page.items), vec![4, 7, 2]);
+  let page = query().after(page.next.unwrap()).page(3).unwrap();
+  assert_eq!(ids(&page.items), vec![5, 8]);
+  assert!(page.next.is_none());
+}
@@ -0,0 +1,104 @@
+use packman::*;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct Config {
+  name: String,
+  max_users: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct User {
+  id: String,
+  name: String,
+}
+
+impl VecPackMember for User {
+  type Out = str;
+  fn get_id(&self) -> &str {
+    &self.id
+  }
+}
+
+#[derive(PackRepository)]
+struct Repository {
+  #[pack(path = "config")]
+  config: Pack<Config>,
+  #[pack(path = "/users")]
+  users: VecPack<User>,
+  counter: u32,
+}
+
+#[test]
+fn test_repository_load_or_init() {
+  let root = PathBuf::from("data/repository_test_load_or_init");
+  let _ = std::fs::remove_dir_all(&root);
+  {
+    let mut repo = Repository::load_or_init(root.clone()).unwrap();
+    repo.config.as_mut().max_users = 12;
+    repo
+      .users
+      .insert(User {
+        id: "1".to_string(),
+        name: "Peter".to_string(),
+      })
+      .unwrap();
+    repo.save_all().unwrap();
+    assert_eq!(repo.counter, 0);
+  }
+  let repo = Repository::load_or_init(root).unwrap();
+  assert_eq!(repo.config.max_users, 12);
+  assert_eq!(repo.users.len(), 1);
+  assert_eq!(repo.users.find_id("1").unwrap().name, "Peter");
+}
+
+#[derive(PackRepository)]
+struct BrokenRepository {
+  #[pack(path = "config")]
+  config: Pack<Config>,
+  #[pack(path = "settings")]
+  settings: Pack<Config>,
+}
+
+#[test]
+fn test_repository_collects_errors() {
+  let root = PathBuf::from("data/repository_test_collects_errors");
+  let _ = std::fs::remove_dir_all(&root);
+  std::fs::create_dir_all(&root).unwrap();
+  std::fs::write(root.join("config"), b"not a packfile").unwrap();
+  std::fs::write(root.join("settings"), b"not a packfile").unwrap();
+  match BrokenRepository::load_or_init(root) {
+    Err(PackError::RepositoryError(errors)) => {
+      assert_eq!(errors.len(), 2);
+      assert_eq!(errors[0].0, "config");
+      assert_eq!(errors[1].0, "settings");
+    }
+    _ => panic!("Expected RepositoryError"),
+  }
+}
+
+#[test]
+fn test_repository_collects_vecpack_errors() {
+  let root = PathBuf::from("data/repository_test_vecpack_errors");
+  let _ = std::fs::remove_dir_all(&root);
+  std::fs::create_dir_all(root.join("users")).unwrap();
+  std::fs::write(root.join("users").join("1"), b"not a packfile").unwrap();
+  match Repository::load_or_init(root.clone()) {
+    Err(PackError::RepositoryError(errors)) => {
+      assert_eq!(errors.len(), 1);
+      assert_eq!(errors[0].0, "users");
+      assert!(matches!(errors[0].1, PackError::MemberLoadError(_, _)));
+    }
+    _ => panic!("Expected RepositoryError"),
+  }
+  // VecPack path is a file
+  std::fs::remove_dir_all(root.join("users")).unwrap();
+  std::fs::write(root.join("users"), b"").unwrap();
+  match Repository::load_or_init(root) {
+    Err(PackError::RepositoryError(errors)) => {
+      assert!(matches!(errors[0].1, PackError::NotADirectory(_)));
+    }
+    _ => panic!("Expected RepositoryError"),
+  }
+}
@@ -0,0 +1,230 @@
+use packman::shared::*;
+use packman::*;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+#[test]
+fn test_shared_pack_concurrent_writers() {
+  let _ = std::fs::remove_dir_all("data/shared_test_writers");
+  let counter: SharedPack<u32> = Pack::<u32>::load_or_init(
+    PathBuf::from("data/shared_test_writers"),
+    "counter",
+  )
+  .unwrap()
+  .into_shared();
+  let threads: Vec<_> = (0..8)
+    .map(|_| {
+      let counter = counter.clone();
+      std::thread::spawn(move || {
+        for _ in 0..10 {
+          *counter.write() += 1;
+          let _ = *counter.read();
+        }
+      })
+    })
+    .collect();
+  threads.into_iter().for_each(|t| t.join().unwrap());
+  assert_eq!(*counter.read(), 80);
+  let loaded: Pack<u32> =
+    Pack::load_or_init(PathBuf::from("data/shared_test_writers"), "counter")
+      .unwrap();
+  assert_eq!(*loaded, 80);
+}
+
+#[test]
+fn test_shared_pack_readers_during_write() {
+  let _ = std::fs::remove_dir_all("data/shared_test_readers");
+  let counter: SharedPack<u32> = Pack::<u32>::load_or_init(
+    PathBuf::from("data/shared_test_readers"),
+    "counter",
+  )
+  .unwrap()
+  .into_shared();
+  let mut guard = counter.write();
+  *guard = 42;
+  // Readers see the old data until the writer is released
+  let reader = counter.clone();
+  let read = std::thread::spawn(move || *reader.read()).join().unwrap();
+  assert_eq!(read, 0);
+  guard.commit().unwrap();
+  assert_eq!(*counter.read(), 42);
+
+  let mut guard = counter.write();
+  *guard = 1;
+  guard.abort();
+  assert_eq!(*counter.read(), 42);
+  assert_eq!(counter.update(|c| *c + 1).unwrap(), 43);
+}
+
+#[test]
+fn test_shared_pack_watch() {
+  let _ = std::fs::remove_dir_all("data/shared_test_watch");
+  let counter: SharedPack<u32> = Pack::<u32>::load_or_init(
+    PathBuf::from("data/shared_test_watch"),
+    "counter",
+  )
+  .unwrap()
+  .into_shared();
+  let (sender, changes) = channel();
+  let watcher = counter.watch(Duration::from_millis(10), move |old, new| {
+    sender.send((*old, *new)).unwrap();
+  });
+  // Own writes are not external changes
+  *counter.write() = 1;
+  // Change from an other process
+  let mut other: Pack<u32> =
+    Pack::load_or_init(PathBuf::from("data/shared_test_watch"), "counter")
+      .unwrap();
+  *other.as_mut() = 42;
+  let change = changes.recv_timeout(Duration::from_secs(5)).unwrap();
+  assert_eq!(change, (1, 42));
+  assert_eq!(*counter.read(), 42);
+  watcher.stop();
+  assert!(changes.try_recv().is_err());
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct Robot {
+  id: u32,
+  name: String,
+}
+
+impl VecPackMember for Robot {
+  type Out = u32;
+  fn get_id(&self) -> &u32 {
+    &self.id
+  }
+}
+
+#[test]
+fn test_shared_vecpack() {
+  let _ = std::fs::remove_dir_all("data/shared_test_vecpack");
+  let robots: SharedVecPack<Robot> =
+    VecPack::load_or_init(PathBuf::from("data/shared_test_vecpack"))
+      .unwrap()
+      .into_shared();
+  let threads: Vec<_> = (0..4)
+    .map(|id| {
+      let robots = robots.clone();
+      std::thread::spawn(move || {
+        robots
+          .insert(Robot {
+            id,
+            name: format!("robot_{}", id),
+          })
+          .unwrap();
+        robots.find_id(&id).unwrap().write().name.push('!');
+      })
+    })
+    .collect();
+  threads.into_iter().for_each(|t| t.join().unwrap());
+  assert_eq!(robots.len(), 4);
+  assert!(robots
+    .insert(Robot {
+      id: 1,
+      name: "other".to_string()
+    })
+    .is_err());
+  // Handle cloned before the removal cannot save
+  let removed = robots.find_id(&0).unwrap();
+  assert_eq!(robots.remove(&0).unwrap().name, "robot_0!");
+  assert!(robots.find_id(&0).is_err());
+  assert!(matches!(
+    removed.update(|r| r.name.push('?')),
+    Err(PackError::ReadOnly)
+  ));
+  assert!(!removed.get_path().exists());
+
+  let loaded: VecPack<Robot> =
+    VecPack::load_or_init(PathBuf::from("data/shared_test_vecpack")).unwrap();
+  assert_eq!(loaded.len(), 3);
+  assert_eq!(loaded.find_id(&2).unwrap().name, "robot_2!");
+}
+
+#[test]
+fn test_shared_vecpack_member_writer() {
+  let _ = std::fs::remove_dir_all("data/shared_test_member_writer");
+  let robots: SharedVecPack<Robot> =
+    VecPack::load_or_init(PathBuf::from("data/shared_test_member_writer"))
+      .unwrap()
+      .into_shared();
+  for id in 1..=2 {
+    robots
+      .insert(Robot {
+        id,
+        name: format!("robot_{}", id),
+      })
+      .unwrap();
+  }
+  // Writer of a member can use the collection,
+  // while remove waits for it
+  let robot = robots.find_id(&1).unwrap();
+  let mut guard = robot.write();
+  guard.name.push('!');
+  let remover = {
+    let robots = robots.clone();
+    std::thread::spawn(move || robots.remove(&1).unwrap())
+  };
+  std::thread::sleep(Duration::from_millis(50));
+  assert_eq!(robots.len(), 2);
+  assert!(robots.find_id(&2).is_ok());
+  guard.commit().unwrap();
+  assert_eq!(remover.join().unwrap().name, "robot_1!");
+  assert_eq!(robots.len(), 1);
+  // Listener can write the member it is notified of
+  let robot = robots.find_id(&2).unwrap();
+  let handle = robot.clone();
+  let _subscription = robots.subscribe(false, move |_| {
+    if !handle.read().name.ends_with('?') {
+      handle.update(|r| r.name.push('?')).unwrap();
+    }
+  });
+  robot.update(|r| r.name.push('!')).unwrap();
+  assert_eq!(robot.read().name, "robot_2!?");
+}
+
+#[test]
+fn test_shared_vecpack_indexes() {
+  let _ = std::fs::remove_dir_all("data/shared_test_indexes");
+  let mut robots: VecPack<Robot> =
+    VecPack::load_or_init(PathBuf::from("data/shared_test_indexes")).unwrap();
+  robots
+    .add_index("name", index::IndexKind::Unique, |r| r.name.clone())
+    .unwrap();
+  let robots = robots.into_shared();
+  for id in 1..=2 {
+    robots
+      .insert(Robot {
+        id,
+        name: format!("robot_{}", id),
+      })
+      .unwrap();
+  }
+  assert!(matches!(
+    robots.insert(Robot {
+      id: 3,
+      name: "robot_1".to_string()
+    }),
+    Err(PackError::UniqueIndexViolation(..))
+  ));
+  let robot = robots.find_id(&2).unwrap();
+  assert!(matches!(
+    robot.update(|r| r.name = "robot_1".to_string()),
+    Err(PackError::UniqueIndexViolation(..))
+  ));
+  assert_eq!(robot.read().name, "robot_2");
+  robot.update(|r| r.name = "robot_3".to_string()).unwrap();
+  assert_eq!(robots.find_by("name", "robot_3").unwrap()[0].read().id, 2);
+  assert!(robots.find_by("name", "robot_2").unwrap().is_empty());
+  // Removed member frees its keys
+  robots.remove(&1).unwrap();
+  robots
+    .insert(Robot {
+      id: 1,
+      name: "robot_1".to_string(),
+    })
+    .unwrap();
+  assert_eq!(robots.find_id(&2).unwrap().read().name, "robot_3");
+}
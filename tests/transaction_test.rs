@@ -0,0 +1,259 @@
+use packman::fs::PackFile;
+use packman::workspace::*;
+use packman::*;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+fn create_workspace(root: &str) -> (Workspace, Pack<u32>, Pack<u32>) {
+  let _ = std::fs::remove_dir_all(root);
+  let mut ws = Workspace::load_or_init(PathBuf::from(root), 7).unwrap();
+  let mut from: Pack<u32> = ws.file_from("warehouse_a").unwrap();
+  let to: Pack<u32> = ws.file_from("warehouse_b").unwrap();
+  *from.as_mut() = 10;
+  (ws, from, to)
+}
+
+fn file_version(path: &str) -> u64 {
+  PackFile::open(&PathBuf::from(path))
+    .unwrap()
+    .metadata()
+    .file_version
+}
+
+fn load(root: &str, name: &str) -> u32 {
+  let mut ws = Workspace::load_or_init(PathBuf::from(root), 7).unwrap();
+  let pack: Pack<u32> = ws.file_from(name).unwrap();
+  pack.into_inner()
+}
+
+#[test]
+fn test_transaction_commit_abort() {
+  let root = "data/transaction_test_commit";
+  let (ws, mut from, mut to) = create_workspace(root);
+  let mut tx = ws.transaction();
+  tx.update(&mut from, |stock| *stock -= 5).unwrap();
+  tx.update(&mut to, |stock| *stock += 5).unwrap();
+  assert_eq!(tx.len(), 2);
+  tx.commit().unwrap();
+  assert_eq!((*from, *to), (5, 5));
+  assert_eq!(load(root, "warehouse_a"), 5);
+  assert_eq!(load(root, "warehouse_b"), 5);
+
+  let mut tx = ws.transaction();
+  tx.update(&mut from, |stock| *stock -= 5).unwrap();
+  tx.update(&mut to, |stock| *stock += 5).unwrap();
+  tx.abort();
+  assert_eq!((*from, *to), (5, 5));
+  assert_eq!(load(root, "warehouse_a"), 5);
+}
+
+#[test]
+fn test_transaction_failed_write_restores() {
+  let root = "data/transaction_test_failed";
+  let (ws, mut from, mut to) = create_workspace(root);
+  // Second packfile can not be written
+  std::fs::remove_file("data/transaction_test_failed/warehouse_b").unwrap();
+  std::fs::create_dir("data/transaction_test_failed/warehouse_b").unwrap();
+  let mut tx = ws.transaction();
+  tx.update(&mut from, |stock| *stock -= 5).unwrap();
+  tx.update(&mut to, |stock| *stock += 5).unwrap();
+  assert!(tx.commit().is_err());
+  assert_eq!((*from, *to), (10, 0));
+  assert_eq!(load(root, "warehouse_a"), 10);
+  assert_eq!(
+    std::fs::read_dir("data/transaction_test_failed/.transactions")
+      .unwrap()
+      .count(),
+    0
+  );
+}
+
+#[test]
+fn test_transaction_recover() {
+  let root = "data/transaction_test_recover";
+  let (ws, _from, _to) = create_workspace(root);
+  drop(ws);
+  let version_a = file_version("data/transaction_test_recover/warehouse_a");
+  let version_b = file_version("data/transaction_test_recover/warehouse_b");
+  // Intent log left by a crashed commit
+  let log_dir = PathBuf::from(root).join(".transactions");
+  std::fs::create_dir_all(&log_dir).unwrap();
+  let log = format!(
+    r#"{{"entries":[
+            {{"path":"warehouse_a","workspace_id":7,"op":"Write",
+              "version":{},"bytes":[53]}},
+            {{"path":"warehouse_b","workspace_id":7,"op":"Write",
+              "version":{},"bytes":[53]}}
+        ]}}"#,
+    version_a, version_b
+  );
+  std::fs::write(log_dir.join("1-1.log"), &log).unwrap();
+  // Incomplete intent log
+  std::fs::write(log_dir.join("1-2.tmp"), "{").unwrap();
+  assert_eq!(load(root, "warehouse_a"), 5);
+  assert_eq!(load(root, "warehouse_b"), 5);
+  assert_eq!(std::fs::read_dir(&log_dir).unwrap().count(), 0);
+  let version_a = file_version("data/transaction_test_recover/warehouse_a");
+  let version_b = file_version("data/transaction_test_recover/warehouse_b");
+
+  // Entries applied before the crash are not written again
+  let mut ws = Workspace::load_or_init(PathBuf::from(root), 7).unwrap();
+  let mut to: Pack<u32> = ws.file_from("warehouse_b").unwrap();
+  *to.as_mut() = 8;
+  drop((to, ws));
+  std::fs::write(log_dir.join("1-3.log"), &log).unwrap();
+  assert_eq!(load(root, "warehouse_a"), 5);
+  assert_eq!(load(root, "warehouse_b"), 8);
+  assert_eq!(
+    file_version("data/transaction_test_recover/warehouse_a"),
+    version_a
+  );
+  assert_eq!(
+    file_version("data/transaction_test_recover/warehouse_b"),
+    version_b + 1
+  );
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct Order {
+  id: u32,
+  stock: u32,
+}
+
+impl VecPackMember for Order {
+  type Out = u32;
+  fn get_id(&self) -> &u32 {
+    &self.id
+  }
+}
+
+#[test]
+fn test_transaction_vecpack_insert_remove() {
+  let root = "data/transaction_test_vecpack";
+  let (mut ws, mut from, _to) = create_workspace(root);
+  let mut open: VecPack<Order> = ws.folder_from("open").unwrap();
+  let mut done: VecPack<Order> = ws.folder_from("done").unwrap();
+  open.insert(Order { id: 1, stock: 5 }).unwrap();
+
+  // Move an order and its stock at once
+  let mut tx = ws.transaction();
+  tx.update(&mut from, |stock| *stock -= 5).unwrap();
+  tx.vecpack(&mut open, |open| open.remove(&1)).unwrap();
+  tx.vecpack(&mut done, |done| done.insert(Order { id: 1, stock: 5 }))
+    .unwrap();
+  tx.commit().unwrap();
+  assert_eq!((open.len(), done.len(), *from), (0, 1, 5));
+  assert!(done.find_id(&1).is_ok());
+
+  // Failed commit restores both folders
+  std::fs::create_dir_all("data/transaction_test_vecpack/open/2").unwrap();
+  let mut tx = ws.transaction();
+  tx.vecpack(&mut done, |done| done.remove(&1)).unwrap();
+  tx.vecpack(&mut open, |open| open.insert(Order { id: 2, stock: 1 }))
+    .unwrap();
+  assert!(tx.commit().is_err());
+  assert_eq!((open.len(), done.len()), (0, 1));
+  std::fs::remove_dir("data/transaction_test_vecpack/open/2").unwrap();
+
+  // Staged changes are checked
+  let mut tx = ws.transaction();
+  assert!(tx.vecpack(&mut open, |open| open.remove(&1)).is_err());
+  assert!(tx
+    .vecpack(&mut done, |done| done.insert(Order { id: 1, stock: 0 }))
+    .is_err());
+  assert!(tx.is_empty());
+  drop(tx);
+
+  let mut ws = Workspace::load_or_init(PathBuf::from(root), 7).unwrap();
+  let open: VecPack<Order> = ws.folder_from("open").unwrap();
+  let done: VecPack<Order> = ws.folder_from("done").unwrap();
+  assert_eq!((open.len(), done.len()), (0, 1));
+}
+
+#[test]
+fn test_transaction_id_change_renames() {
+  let root = "data/transaction_test_id_change";
+  let (mut ws, _from, _to) = create_workspace(root);
+  let mut orders: VecPack<Order> = ws.folder_from("orders").unwrap();
+  orders.insert(Order { id: 1, stock: 5 }).unwrap();
+  let mut tx = ws.transaction();
+  tx.update(orders.find_id_mut(&1).unwrap(), |order| order.id = 7)
+    .unwrap();
+  tx.commit().unwrap();
+  let dir = PathBuf::from(root).join("orders");
+  assert!(!dir.join("1").exists());
+  assert_eq!(orders.find_id(&7).unwrap().get_path(), dir.join("7"));
+
+  let mut ws = Workspace::load_or_init(PathBuf::from(root), 7).unwrap();
+  let orders: VecPack<Order> = ws.folder_from("orders").unwrap();
+  assert_eq!(orders.find_id(&7).unwrap().stock, 5);
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct Warehouse {
+  id: u32,
+  stock: u32,
+}
+
+impl VecPackMember for Warehouse {
+  type Out = u32;
+  fn get_id(&self) -> &u32 {
+    &self.id
+  }
+}
+
+#[test]
+fn test_transaction_vecpack_members() {
+  let root = "data/transaction_test_members";
+  let (mut ws, _from, _to) = create_workspace(root);
+  let mut warehouses: VecPack<Warehouse> =
+    ws.folder_from("warehouses").unwrap();
+  warehouses.insert(Warehouse { id: 1, stock: 10 }).unwrap();
+  warehouses.insert(Warehouse { id: 2, stock: 0 }).unwrap();
+  warehouses.insert(Warehouse { id: 3, stock: 1 }).unwrap();
+  let (_subscription, events) = warehouses.subscribe_channel(false);
+
+  // Move stock between two members
+  let mut tx = ws.transaction();
+  tx.vecpack(&mut warehouses, |warehouses| {
+    warehouses.update(&1, |w| w.stock -= 5)?;
+    warehouses.update(&2, |w| w.stock += 5)
+  })
+  .unwrap();
+  assert_eq!(tx.len(), 2);
+  tx.commit().unwrap();
+  assert_eq!(warehouses.find_id(&1).unwrap().stock, 5);
+  assert_eq!(warehouses.find_id(&2).unwrap().stock, 5);
+  assert_eq!(events.try_iter().count(), 2);
+
+  // Staged members are changed again, and IDs are
+  // checked against the staged changes
+  let mut tx = ws.transaction();
+  tx.vecpack(&mut warehouses, |warehouses| {
+    warehouses.remove(&3)?;
+    warehouses.update(&1, |w| w.id = 3)?;
+    assert!(matches!(
+      warehouses.update(&2, |w| w.id = 3),
+      Err(PackError::IDTaken)
+    ));
+    warehouses.insert(Warehouse { id: 4, stock: 0 })?;
+    warehouses.update(&4, |w| w.stock = 5)?;
+    warehouses.update(&2, |w| w.id = 1)
+  })
+  .unwrap();
+  tx.commit().unwrap();
+  let ids = |warehouses: &VecPack<Warehouse>| {
+    let mut ids: Vec<(u32, u32)> =
+      warehouses.iter().map(|w| (w.id, w.stock)).collect();
+    ids.sort();
+    ids
+  };
+  assert_eq!(ids(&warehouses), vec![(1, 5), (3, 5), (4, 5)]);
+  let dir = PathBuf::from(root).join("warehouses");
+  assert!(!dir.join("2").exists());
+  assert_eq!(warehouses.find_id(&3).unwrap().get_path(), dir.join("3"));
+
+  let mut ws = Workspace::load_or_init(PathBuf::from(root), 7).unwrap();
+  let warehouses: VecPack<Warehouse> = ws.folder_from("warehouses").unwrap();
+  assert_eq!(ids(&warehouses), vec![(1, 5), (3, 5), (4, 5)]);
+}
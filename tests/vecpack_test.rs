@@ -178,3 +178,530 @@ fn test_mut_ref() {
   robots.get_mut(0).unwrap().as_mut().name = "Mini Roboto".to_string();
   assert_eq!(robots.get(0).unwrap().name, "Mini Roboto");
 }
+
+#[test]
+fn test_vecpack_move_item() {
+  let _ = std::fs::remove_dir_all("data/vecpack_test_move_item");
+  let mut cars =
+    create_dummy_vecpack(PathBuf::from("data/vecpack_test_move_item/a"));
+  let mut other: VecPack<Car> =
+    VecPack::load_or_init(PathBuf::from("data/vecpack_test_move_item/b"))
+      .unwrap();
+  other
+    .insert(Car::new("3".to_string(), "CarOther".to_string(), 100))
+    .unwrap();
+  cars.move_item("1", &mut other).unwrap();
+  assert_eq!(cars.len(), 2);
+  assert_eq!(other.find_id("1").unwrap().hp, 150);
+  // ID taken in the target
+  assert_eq!(cars.move_item("3", &mut other).is_err(), true);
+  assert_eq!(cars.find_id("3").unwrap().name, "CarMedium");
+  assert_eq!(cars.move_item("100", &mut other).is_err(), true);
+
+  let cars: VecPack<Car> =
+    VecPack::load_or_init(PathBuf::from("data/vecpack_test_move_item/a"))
+      .unwrap();
+  let other: VecPack<Car> =
+    VecPack::load_or_init(PathBuf::from("data/vecpack_test_move_item/b"))
+      .unwrap();
+  assert_eq!(cars.len(), 2);
+  assert_eq!(other.len(), 2);
+  assert_eq!(other.find_id("3").unwrap().name, "CarOther");
+}
+
+// No Default implementation
+#[derive(Serialize, Deserialize, Clone)]
+struct Plan {
+  pub id: u32,
+  pub name: String,
+}
+
+impl VecPackMember for Plan {
+  type Out = u32;
+  fn get_id(&self) -> &u32 {
+    &self.id
+  }
+}
+
+#[test]
+fn test_vecpack_load_or_init_with() {
+  let _ = std::fs::remove_dir_all("data/vecpack_test_init_with");
+  let plans: VecPack<Plan> = VecPack::load_or_init_with(
+    PathBuf::from("data/vecpack_test_init_with"),
+    || {
+      vec![
+        Plan {
+          id: 1,
+          name: "Basic".to_string(),
+        },
+        Plan {
+          id: 2,
+          name: "Pro".to_string(),
+        },
+      ]
+    },
+  )
+  .unwrap();
+  assert_eq!(plans.len(), 2);
+  // Existing directory is only loaded
+  let plans: VecPack<Plan> = VecPack::load_or_init_with(
+    PathBuf::from("data/vecpack_test_init_with"),
+    Vec::new,
+  )
+  .unwrap();
+  assert_eq!(plans.len(), 2);
+  assert_eq!(plans.find_id(&2).unwrap().name, "Pro");
+}
+
+#[test]
+fn test_vecpack_max_open_files() {
+  let _ = std::fs::remove_dir_all("data/vecpack_test_open_files");
+  let mut robots: VecPack<Robot> =
+    VecPack::load_or_init(PathBuf::from("data/vecpack_test_open_files"))
+      .unwrap();
+  robots.set_max_open_files(2);
+  for id in 0..5 {
+    robots
+      .insert(Robot::new(id, format!("robot_{}", id), false))
+      .unwrap();
+  }
+  assert_eq!(robots.open_files(), 2);
+  for robot in &mut robots {
+    robot.as_mut().can_speak = true;
+  }
+  assert_eq!(robots.open_files(), 2);
+  assert_eq!(robots.max_open_files(), 2);
+
+  let robots: VecPack<Robot> =
+    VecPack::load_or_init(PathBuf::from("data/vecpack_test_open_files"))
+      .unwrap();
+  assert_eq!(robots.len(), 5);
+  assert!(robots.iter().all(|robot| robot.can_speak));
+}
+
+#[test]
+fn test_vecpack_id_index() {
+  let _ = std::fs::remove_dir_all("data/vecpack_test_id_index");
+  let mut robots: VecPack<Robot> =
+    VecPack::load_or_init(PathBuf::from("data/vecpack_test_id_index")).unwrap();
+  for id in 0..1000 {
+    robots
+      .insert(Robot::new(id, format!("robot_{}", id), false))
+      .unwrap();
+  }
+  assert_eq!(robots.check_id_available(&999), false);
+  assert_eq!(robots.find_id(&500).unwrap().name, "robot_500");
+  robots.remove_pack(&0).unwrap();
+  assert_eq!(robots.find_id(&999).unwrap().name, "robot_999");
+
+  // Guard changes the ID
+  robots.find_id_mut(&1).unwrap().as_mut().id = 1000;
+  assert!(robots.find_id(&1).is_err());
+  assert_eq!(robots.find_id(&1000).unwrap().name, "robot_1");
+  assert_eq!(robots.check_id_available(&1), true);
+  // ID taken by an other member
+  let res = robots.find_id_mut(&2).unwrap().update(|r| r.id = 3);
+  assert!(match res {
+    Err(PackError::IDTaken) => true,
+    _ => false,
+  });
+  assert_eq!(robots.find_id(&2).unwrap().name, "robot_2");
+  assert_eq!(robots.find_id(&3).unwrap().name, "robot_3");
+
+  // Changes through the Vec are indexed again
+  robots.as_vec_mut().swap(0, 1);
+  assert_eq!(robots.find_id(&2).unwrap().name, "robot_2");
+  assert_eq!(robots.find_id(&1000).unwrap().name, "robot_1");
+
+  // ID changes are checked while the index is stale
+  let members = robots.as_vec_mut();
+  let res = members[0].update(|r| r.id = 3);
+  assert!(matches!(res, Err(PackError::IDTaken)));
+  members[0].update(|r| r.id = 1001).unwrap();
+  assert!(robots.find_id(&1001).is_ok());
+  assert!(robots.find_id(&2).is_err());
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Customer {
+  pub id: u32,
+  pub email: String,
+  pub status: String,
+}
+
+impl Customer {
+  pub fn new(id: u32, email: &str, status: &str) -> Self {
+    Customer {
+      id,
+      email: email.to_string(),
+      status: status.to_string(),
+    }
+  }
+}
+
+impl VecPackMember for Customer {
+  type Out = u32;
+  fn get_id(&self) -> &u32 {
+    &self.id
+  }
+}
+
+#[test]
+fn test_vecpack_secondary_index() {
+  use packman::index::IndexKind;
+  let _ = std::fs::remove_dir_all("data/vecpack_test_secondary_index");
+  let mut customers: VecPack<Customer> =
+    VecPack::load_or_init(PathBuf::from("data/vecpack_test_secondary_index"))
+      .unwrap();
+  customers
+    .insert(Customer::new(1, "a@example.com", "active"))
+    .unwrap();
+  customers
+    .add_index("email", IndexKind::Unique, |c| c.email.clone())
+    .unwrap();
+  customers
+    .add_index("status", IndexKind::Multi, |c| c.status.clone())
+    .unwrap();
+  customers
+    .insert(Customer::new(2, "b@example.com", "active"))
+    .unwrap();
+  customers
+    .insert(Customer::new(3, "c@example.com", "inactive"))
+    .unwrap();
+  assert_eq!(customers.find_by("status", "active").unwrap().len(), 2);
+  assert_eq!(
+    customers.find_by("email", "c@example.com").unwrap()[0].id,
+    3
+  );
+  assert!(customers.find_by("name", "x").is_err());
+
+  // Unique key taken on insert
+  let res = customers.insert(Customer::new(4, "a@example.com", "active"));
+  assert!(match res {
+    Err(PackError::UniqueIndexViolation(index, key)) =>
+      index == "email" && key == "a@example.com",
+    _ => false,
+  });
+  assert_eq!(customers.len(), 3);
+
+  // Saves update the indexes
+  customers.find_id_mut(&2).unwrap().as_mut().status = "inactive".into();
+  assert_eq!(customers.find_by("status", "active").unwrap().len(), 1);
+  assert_eq!(customers.find_by("status", "inactive").unwrap().len(), 2);
+  let res = customers
+    .find_id_mut(&2)
+    .unwrap()
+    .update(|c| c.email = "c@example.com".into());
+  assert!(res.is_err());
+  assert_eq!(
+    customers.find_by("email", "b@example.com").unwrap()[0].id,
+    2
+  );
+  // Unique keys are checked while the indexes are stale
+  let res =
+    customers.as_vec_mut()[0].update(|c| c.email = "c@example.com".into());
+  assert!(matches!(res, Err(PackError::UniqueIndexViolation(_, _))));
+  customers.remove_pack(&1).unwrap();
+  assert!(customers
+    .find_by("email", "a@example.com")
+    .unwrap()
+    .is_empty());
+  assert_eq!(
+    customers.find_by("email", "c@example.com").unwrap()[0].id,
+    3
+  );
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Ticket {
+  id: u64,
+  title: String,
+}
+
+impl VecPackMember for Ticket {
+  type Out = u64;
+  fn get_id(&self) -> &u64 {
+    &self.id
+  }
+}
+
+#[test]
+fn test_vecpack_insert_new_counter() {
+  let path = PathBuf::from("data/vecpack_test_insert_new_counter");
+  let _ = std::fs::remove_dir_all(&path);
+  let mut tickets: VecPack<Ticket> =
+    VecPack::load_or_init(path.clone()).unwrap();
+  tickets.set_id_strategy(id::IdStrategy::Counter).unwrap();
+  let new_ticket = |id: String| Ticket {
+    id: id.parse().unwrap(),
+    title: "title".into(),
+  };
+  assert_eq!(tickets.insert_new(new_ticket).unwrap().id, 1);
+  // Taken IDs are skipped
+  tickets
+    .insert(Ticket {
+      id: 2,
+      title: "by hand".into(),
+    })
+    .unwrap();
+  assert_eq!(tickets.insert_new(new_ticket).unwrap().id, 3);
+  drop(tickets);
+
+  // Counter file is not a member, and it survives
+  // the reload with the strategy
+  let mut tickets: VecPack<Ticket> =
+    VecPack::load_or_init(path.clone()).unwrap();
+  assert_eq!(tickets.len(), 3);
+  assert!(matches!(tickets.id_strategy(), id::IdStrategy::Counter));
+  tickets.remove_pack(&3).unwrap();
+  assert_eq!(tickets.insert_new(new_ticket).unwrap().id, 4);
+  drop(tickets);
+
+  // Handles of the same directory never get the same value
+  let workers = (0..4)
+    .map(|_| {
+      let path = path.clone();
+      std::thread::spawn(move || {
+        let mut tickets: VecPack<Ticket> = VecPack::load_or_init(path).unwrap();
+        for _ in 0..25 {
+          tickets.insert_new(new_ticket).unwrap();
+        }
+      })
+    })
+    .collect::<Vec<_>>();
+  for worker in workers {
+    worker.join().unwrap();
+  }
+  let tickets: VecPack<Ticket> = VecPack::load_or_init(path).unwrap();
+  assert_eq!(tickets.len(), 103);
+  assert!(tickets.find_id(&104).is_ok());
+}
+
+#[test]
+fn test_vecpack_insert_new_random() {
+  let path = PathBuf::from("data/vecpack_test_insert_new_random");
+  let _ = std::fs::remove_dir_all(&path);
+  let mut cars: VecPack<Car> = VecPack::load_or_init(path).unwrap();
+  let id = cars
+    .insert_new(|id| Car::new(id, "Ford".into(), 100))
+    .unwrap()
+    .id
+    .clone();
+  assert_eq!(id.len(), 21);
+  cars.set_id_strategy(id::IdStrategy::Uuid).unwrap();
+  let id = cars
+    .insert_new(|id| Car::new(id, "Opel".into(), 80))
+    .unwrap()
+    .id
+    .clone();
+  assert_eq!(id.len(), 36);
+  // Custom generator always returning a taken ID
+  cars
+    .set_id_strategy(id::IdStrategy::custom(move || id.clone()))
+    .unwrap();
+  let res = cars.insert_new(|id| Car::new(id, "BMW".into(), 150));
+  assert!(matches!(res, Err(PackError::IDTaken)));
+  assert_eq!(cars.len(), 2);
+}
+
+#[test]
+fn test_vecpack_id_filename_encoding() {
+  let path = PathBuf::from("data/vecpack_test_id_filename");
+  let _ = std::fs::remove_dir_all(&path);
+  let ids = ["a/../b", "..", "CON", "x y ", "50%", "Ford"];
+  let mut cars: VecPack<Car> = VecPack::load_or_init(path.clone()).unwrap();
+  for id in ids {
+    cars.insert(Car::new(id.into(), "car".into(), 100)).unwrap();
+  }
+  // Every member file is inside the directory
+  let mut names = std::fs::read_dir(&path)
+    .unwrap()
+    .map(|e| e.unwrap().file_name().into_string().unwrap())
+    .collect::<Vec<String>>();
+  names.sort();
+  assert_eq!(
+    names,
+    vec!["%2E%2E", "%43ON", "50%25", "Ford", "a%2F..%2Fb", "x y%20"]
+  );
+  // IDs differing only in case collide
+  let res = cars.insert(Car::new("ford".into(), "car".into(), 100));
+  assert!(match res {
+    Err(PackError::IDCollision(id, taken)) => id == "ford" && taken == "Ford",
+    _ => false,
+  });
+  drop(cars);
+
+  let cars: VecPack<Car> = VecPack::load_or_init(path.clone()).unwrap();
+  assert_eq!(cars.len(), ids.len());
+  for id in ids {
+    assert!(cars.find_id(id).is_ok());
+  }
+  // Filename must match the ID inside the file
+  std::fs::copy(path.join("Ford"), path.join("Opel")).unwrap();
+  let res = VecPack::<Car>::load_read_only(path);
+  assert!(match res {
+    Err(PackError::IDMismatch(name, id)) => name == "Opel" && id == "Ford",
+    _ => false,
+  });
+}
+
+#[test]
+fn test_vecpack_id_change_renames() {
+  let path = PathBuf::from("data/vecpack_test_id_change");
+  let _ = std::fs::remove_dir_all(&path);
+  let mut cars: VecPack<Car> = VecPack::load_or_init(path.clone()).unwrap();
+  cars
+    .insert(Car::new("Ford".into(), "car".into(), 100))
+    .unwrap();
+  cars
+    .insert(Car::new("Opel".into(), "car".into(), 80))
+    .unwrap();
+  cars.find_id_mut("Ford").unwrap().as_mut().id = "a:b".into();
+  assert!(!path.join("Ford").exists());
+  assert!(path.join("a%3Ab").is_file());
+  assert_eq!(cars.find_id("a:b").unwrap().get_path(), path.join("a%3Ab"));
+  // Taken ID keeps the file
+  let res = cars
+    .find_id_mut("Opel")
+    .unwrap()
+    .update(|c| c.id = "a:b".into());
+  assert!(matches!(res, Err(PackError::IDTaken)));
+  assert!(path.join("Opel").is_file());
+  drop(cars);
+
+  let cars: VecPack<Car> = VecPack::load_or_init(path.clone()).unwrap();
+  assert_eq!(cars.find_id("a:b").unwrap().hp, 100);
+}
+
+#[test]
+fn test_vecpack_legacy_filenames() {
+  let path = PathBuf::from("data/vecpack_test_legacy_filenames");
+  let _ = std::fs::remove_dir_all(&path);
+  let ids = ["50%", "a:b", "CON", ".x"];
+  let mut cars: VecPack<Car> = VecPack::load_or_init(path.clone()).unwrap();
+  for id in ids {
+    cars.insert(Car::new(id.into(), "car".into(), 100)).unwrap();
+  }
+  drop(cars);
+  // Files named by the raw ID, before IDs were encoded
+  for id in ids {
+    let encoded = path.join(id::encode_filename(id));
+    std::fs::rename(encoded, path.join(id)).unwrap();
+  }
+  // Other dotfiles are not members
+  std::fs::write(path.join(".DS_Store"), "finder").unwrap();
+
+  // Loading does not rename them
+  let res = VecPack::<Car>::load_or_init(path.clone());
+  assert!(matches!(res, Err(PackError::MemberLoadError(_, _))));
+  let (cars, failures) =
+    VecPack::<Car>::load_or_init_lenient(path.clone()).unwrap();
+  assert!(cars.is_empty());
+  assert_eq!(failures.len(), ids.len());
+  assert!(failures.iter().all(|f| matches!(
+    f.error,
+    PackError::IDMismatch(_, _)
+  ) && f.quarantined.is_none()
+    && f.path.is_file()));
+  drop(cars);
+
+  assert_eq!(VecPack::<Car>::migrate_filenames(path.clone()).unwrap(), 4);
+  let cars: VecPack<Car> = VecPack::load_or_init(path.clone()).unwrap();
+  assert_eq!(cars.len(), ids.len());
+  for id in ids {
+    assert!(!path.join(id).exists());
+    assert_eq!(
+      cars.find_id(id).unwrap().get_path(),
+      path.join(id::encode_filename(id))
+    );
+  }
+  assert!(path.join(".DS_Store").is_file());
+}
+
+#[test]
+fn test_vecpack_sharded_layout() {
+  let path = PathBuf::from("data/vecpack_test_sharded_layout");
+  let _ = std::fs::remove_dir_all(&path);
+  let mut tickets: VecPack<Ticket> =
+    VecPack::load_or_init(path.clone()).unwrap();
+  assert_eq!(tickets.layout(), layout::Layout::Flat);
+  for id in 0..20 {
+    tickets
+      .insert(Ticket {
+        id,
+        title: format!("ticket_{}", id),
+      })
+      .unwrap();
+  }
+  let depth =
+    |p: &std::path::Path| p.strip_prefix(&path).unwrap().components().count();
+  tickets.set_layout(layout::Layout::sharded(2)).unwrap();
+  assert!(tickets.iter().all(|t| depth(t.get_path()) == 3));
+  assert!(path.join(layout::MANIFEST_FILE).is_file());
+  tickets
+    .insert(Ticket {
+      id: 20,
+      title: "ticket_20".into(),
+    })
+    .unwrap();
+  assert_eq!(depth(tickets.find_id(&20).unwrap().get_path()), 3);
+  // Interrupted migration: a member is left flat
+  let moved = tickets.find_id(&7).unwrap().get_path().to_path_buf();
+  drop(tickets);
+  std::fs::rename(&moved, path.join("7")).unwrap();
+
+  let mut tickets: VecPack<Ticket> =
+    VecPack::load_or_init(path.clone()).unwrap();
+  assert_eq!(tickets.layout(), layout::Layout::sharded(2));
+  assert_eq!(tickets.len(), 21);
+  // Loading does not move the member, set_layout() does
+  assert_eq!(tickets.find_id(&7).unwrap().get_path(), path.join("7"));
+  tickets.set_layout(layout::Layout::sharded(2)).unwrap();
+  assert_eq!(tickets.find_id(&7).unwrap().get_path(), moved.as_path());
+  assert!(!path.join("7").exists());
+
+  // Back to flat, shard directories are removed
+  // but other directories are left alone
+  std::fs::create_dir_all(path.join("notes/empty")).unwrap();
+  std::fs::write(path.join("notes/todo"), "not a member").unwrap();
+  tickets.set_layout(layout::Layout::Flat).unwrap();
+  assert!(tickets.iter().all(|t| depth(t.get_path()) == 1));
+  let dirs = std::fs::read_dir(&path)
+    .unwrap()
+    .map(|e| e.unwrap())
+    .filter(|e| e.file_type().unwrap().is_dir())
+    .map(|e| e.file_name())
+    .collect::<Vec<_>>();
+  assert_eq!(dirs, vec!["notes"]);
+  assert!(path.join("notes/empty").is_dir());
+  drop(tickets);
+  let tickets: VecPack<Ticket> = VecPack::load_read_only(path).unwrap();
+  assert_eq!(tickets.len(), 21);
+  assert_eq!(tickets.find_id(&20).unwrap().title, "ticket_20");
+}
+
+#[test]
+fn test_vecpack_sharded_layout_numeric_ids() {
+  let path = PathBuf::from("data/vecpack_test_sharded_numeric");
+  let _ = std::fs::remove_dir_all(&path);
+  let mut tickets: VecPack<Ticket> =
+    VecPack::load_or_init(path.clone()).unwrap();
+  // Flat member files named like two hex digits
+  for id in 0..300 {
+    tickets
+      .insert(Ticket {
+        id,
+        title: format!("ticket_{}", id),
+      })
+      .unwrap();
+  }
+  tickets.set_layout(layout::Layout::sharded(1)).unwrap();
+  assert!(tickets
+    .iter()
+    .all(|t| t.get_path().parent().unwrap() != path.as_path()));
+  let moved = tickets.find_id(&42).unwrap().get_path().to_path_buf();
+  drop(tickets);
+  let tickets: VecPack<Ticket> = VecPack::load_or_init(path).unwrap();
+  assert_eq!(tickets.len(), 300);
+  assert_eq!(tickets.find_id(&42).unwrap().get_path(), moved.as_path());
+}
@@ -0,0 +1,170 @@
+use packman::fs::PackFile;
+use packman::workspace::*;
+use packman::*;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct Config {
+  name: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct User {
+  id: u32,
+  name: String,
+}
+
+impl VecPackMember for User {
+  type Out = u32;
+  fn get_id(&self) -> &u32 {
+    &self.id
+  }
+}
+
+fn create_workspace(root: &str) -> Workspace {
+  let _ = std::fs::remove_dir_all(root);
+  let mut ws = Workspace::load_or_init(PathBuf::from(root), 7).unwrap();
+  let mut config: Pack<Config> = ws.file_from("config").unwrap();
+  config.as_mut().name = "demo".to_string();
+  let mut users: VecPack<User> = ws.folder_from("users").unwrap();
+  users
+    .insert(User {
+      id: 1,
+      name: "Peter".to_string(),
+    })
+    .unwrap();
+  users
+    .insert(User {
+      id: 2,
+      name: "Kriszti".to_string(),
+    })
+    .unwrap();
+  ws
+}
+
+#[test]
+fn test_workspace_load_or_init() {
+  let ws = create_workspace("data/workspace_test_load_or_init");
+  assert_eq!(ws.objects().len(), 2);
+  assert_eq!(ws.objects()[1].kind, ObjectKind::Folder);
+  drop(ws);
+
+  let mut ws = Workspace::load_or_init(
+    PathBuf::from("data/workspace_test_load_or_init"),
+    7,
+  )
+  .unwrap();
+  assert_eq!(ws.objects().len(), 2);
+  let config: Pack<Config> = ws.file_from("config").unwrap();
+  assert_eq!(config.name, "demo");
+  assert!(ws.folder_from::<User>("config").is_err());
+  assert!(ws.file_from::<Config>("../config").is_err());
+
+  let other = Workspace::load_or_init(
+    PathBuf::from("data/workspace_test_load_or_init"),
+    8,
+  );
+  assert!(match other {
+    Err(PackError::WorkspaceMismatch(8, Some(7))) => true,
+    _ => false,
+  });
+}
+
+#[test]
+fn test_workspace_id_in_superblock() {
+  let _ws = create_workspace("data/workspace_test_superblock");
+  let config =
+    PackFile::open(&PathBuf::from("data/workspace_test_superblock/config"))
+      .unwrap();
+  assert_eq!(config.get_workspace_id(), Some(7));
+  let user =
+    PackFile::open(&PathBuf::from("data/workspace_test_superblock/users/1"))
+      .unwrap();
+  assert_eq!(user.get_workspace_id(), Some(7));
+}
+
+#[test]
+fn test_workspace_verify_and_stats() {
+  let ws = create_workspace("data/workspace_test_verify");
+  let report = ws.verify().unwrap();
+  assert!(report.is_ok());
+  assert_eq!(report.checked, 4);
+
+  // Foreign packfile in the users folder
+  let _: Pack<User> =
+    Pack::load_or_init(PathBuf::from("data/workspace_test_verify/users"), "3")
+      .unwrap();
+  let report = ws.verify().unwrap();
+  assert_eq!(report.errors.len(), 1);
+
+  let stats = ws.stats().unwrap();
+  assert_eq!(stats.objects.len(), 2);
+  assert_eq!(stats.objects[1].files, 3);
+  assert_eq!(stats.files, 4);
+  assert!(stats.bytes > 0);
+}
+
+#[test]
+fn test_workspace_backup() {
+  let ws = create_workspace("data/workspace_test_backup");
+  let _ = std::fs::remove_dir_all("data/workspace_test_backup_copy");
+  assert!(ws
+    .backup(PathBuf::from("data/workspace_test_backup/copy"))
+    .is_err());
+  let absolute = std::env::current_dir()
+    .unwrap()
+    .join("data/workspace_test_backup/./users/copy");
+  assert!(ws.backup(absolute).is_err());
+  ws.backup(PathBuf::from("data/workspace_test_backup_copy"))
+    .unwrap();
+  let mut copy = Workspace::load_or_init(
+    PathBuf::from("data/workspace_test_backup_copy"),
+    7,
+  )
+  .unwrap();
+  let users: VecPack<User> = copy.folder_from("users").unwrap();
+  assert_eq!(users.find_id(&2).unwrap().name, "Kriszti");
+}
+
+#[test]
+fn test_workspace_register_on_load() {
+  let mut ws = create_workspace("data/workspace_test_register_on_load");
+  std::fs::write(
+    "data/workspace_test_register_on_load/broken",
+    "not a packfile",
+  )
+  .unwrap();
+  assert!(ws.file_from::<Config>("broken").is_err());
+  assert!(ws.objects().iter().all(|o| o.name != "broken"));
+  assert_eq!(ws.objects().len(), 2);
+}
+
+#[test]
+fn test_workspace_read_only() {
+  let ws = create_workspace("data/workspace_test_read_only");
+  drop(ws);
+  let mut ws = Workspace::open_read_only(
+    PathBuf::from("data/workspace_test_read_only"),
+    7,
+  )
+  .unwrap();
+  assert!(ws.is_read_only());
+  let mut users: VecPack<User> = ws.folder_from("users").unwrap();
+  assert_eq!(users.len(), 2);
+  assert!(match users.insert(User {
+    id: 3,
+    name: "Other".to_string(),
+  }) {
+    Err(PackError::ReadOnly) => true,
+    _ => false,
+  });
+  assert!(users.remove_pack(&1).is_err());
+  assert!(users.find_id_mut(&1).unwrap().update(|u| u.id = 4).is_err());
+  assert!(match ws.file_from::<Config>("other") {
+    Err(PackError::ObjectNotFound) => true,
+    _ => false,
+  });
+  assert_eq!(ws.objects().len(), 2);
+  assert!(ws.verify().unwrap().is_ok());
+}
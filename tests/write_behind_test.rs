@@ -0,0 +1,202 @@
+use packman::fs::PackFile;
+use packman::write_behind::*;
+use packman::*;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn file_version(path: &str) -> u64 {
+  PackFile::open(&PathBuf::from(path))
+    .unwrap()
+    .metadata()
+    .file_version
+}
+
+#[test]
+fn test_write_behind_coalesce() {
+  let _ = std::fs::remove_dir_all("data/write_behind_test_coalesce");
+  let (writer, _errors) = WriteBehind::start();
+  let mut counter: Pack<u32> = Pack::load_or_init(
+    PathBuf::from("data/write_behind_test_coalesce"),
+    "counter",
+  )
+  .unwrap();
+  let version = file_version("data/write_behind_test_coalesce/counter");
+  counter.enable_write_behind(
+    &writer.handle(),
+    Duration::from_secs(10),
+    Duration::from_secs(60),
+  );
+  for _ in 0..100 {
+    *counter.as_mut() += 1;
+  }
+  assert_eq!(
+    file_version("data/write_behind_test_coalesce/counter"),
+    version
+  );
+  counter.flush().unwrap();
+  assert_eq!(
+    file_version("data/write_behind_test_coalesce/counter"),
+    version + 1
+  );
+  let counter: Pack<u32> = Pack::load_or_init(
+    PathBuf::from("data/write_behind_test_coalesce"),
+    "counter",
+  )
+  .unwrap();
+  assert_eq!(*counter, 100);
+}
+
+#[test]
+fn test_write_behind_debounce() {
+  let _ = std::fs::remove_dir_all("data/write_behind_test_debounce");
+  let (writer, _errors) = WriteBehind::start();
+  let mut counter: Pack<u32> = Pack::load_or_init(
+    PathBuf::from("data/write_behind_test_debounce"),
+    "counter",
+  )
+  .unwrap();
+  counter.enable_write_behind(
+    &writer.handle(),
+    Duration::from_millis(10),
+    Duration::from_millis(50),
+  );
+  *counter.as_mut() = 42;
+  std::thread::sleep(Duration::from_millis(300));
+  let loaded: Pack<u32> = Pack::load_or_init(
+    PathBuf::from("data/write_behind_test_debounce"),
+    "counter",
+  )
+  .unwrap();
+  assert_eq!(*loaded, 42);
+}
+
+#[test]
+fn test_write_behind_shutdown_flush() {
+  let _ = std::fs::remove_dir_all("data/write_behind_test_shutdown");
+  let (writer, _errors) = WriteBehind::start();
+  let mut counter: Pack<u32> = Pack::load_or_init(
+    PathBuf::from("data/write_behind_test_shutdown"),
+    "counter",
+  )
+  .unwrap();
+  counter.enable_write_behind(
+    &writer.handle(),
+    Duration::from_secs(10),
+    Duration::from_secs(60),
+  );
+  *counter.as_mut() = 42;
+  writer.shutdown();
+  let loaded: Pack<u32> = Pack::load_or_init(
+    PathBuf::from("data/write_behind_test_shutdown"),
+    "counter",
+  )
+  .unwrap();
+  assert_eq!(*loaded, 42);
+  // Worker is stopped, save falls back to synchronous write
+  *counter.as_mut() = 43;
+  assert!(counter.flush().is_err());
+  let loaded: Pack<u32> = Pack::load_or_init(
+    PathBuf::from("data/write_behind_test_shutdown"),
+    "counter",
+  )
+  .unwrap();
+  assert_eq!(*loaded, 43);
+}
+
+#[test]
+fn test_write_behind_error_channel() {
+  let _ = std::fs::remove_dir_all("data/write_behind_test_errors");
+  let (writer, errors) = WriteBehind::start();
+  let mut counter: Pack<u32> = Pack::load_or_init(
+    PathBuf::from("data/write_behind_test_errors"),
+    "counter",
+  )
+  .unwrap();
+  counter.enable_write_behind(
+    &writer.handle(),
+    Duration::from_secs(10),
+    Duration::from_secs(60),
+  );
+  std::fs::remove_file("data/write_behind_test_errors/counter").unwrap();
+  std::fs::create_dir("data/write_behind_test_errors/counter").unwrap();
+  *counter.as_mut() = 42;
+  writer.flush_all().unwrap();
+  let error = errors.try_recv().unwrap();
+  assert_eq!(
+    error.path,
+    PathBuf::from("data/write_behind_test_errors/counter")
+  );
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct Robot {
+  id: u32,
+  name: String,
+}
+
+impl VecPackMember for Robot {
+  type Out = u32;
+  fn get_id(&self) -> &u32 {
+    &self.id
+  }
+}
+
+#[test]
+fn test_write_behind_vecpack() {
+  let _ = std::fs::remove_dir_all("data/write_behind_test_vecpack");
+  let (writer, _errors) = WriteBehind::start();
+  let mut robots: VecPack<Robot> =
+    VecPack::load_or_init(PathBuf::from("data/write_behind_test_vecpack"))
+      .unwrap();
+  robots.enable_write_behind(
+    &writer.handle(),
+    Duration::from_secs(10),
+    Duration::from_secs(60),
+  );
+  for id in 0..10 {
+    robots
+      .insert(Robot {
+        id,
+        name: format!("robot_{}", id),
+      })
+      .unwrap();
+  }
+  robots.into_iter().for_each(|r| r.as_mut().name.push('!'));
+  robots.flush().unwrap();
+  let robots: VecPack<Robot> =
+    VecPack::load_or_init(PathBuf::from("data/write_behind_test_vecpack"))
+      .unwrap();
+  assert_eq!(robots.len(), 10);
+  assert_eq!(robots.find_id(&3).unwrap().name, "robot_3!");
+}
+
+#[test]
+fn test_write_behind_events() {
+  let _ = std::fs::remove_dir_all("data/write_behind_test_events");
+  let (writer, _errors) = WriteBehind::start();
+  let mut counter: Pack<u32> = Pack::load_or_init(
+    PathBuf::from("data/write_behind_test_events"),
+    "counter",
+  )
+  .unwrap();
+  let version = counter.version();
+  let (_subscription, events) = counter.subscribe_channel(false);
+  counter.enable_write_behind(
+    &writer.handle(),
+    Duration::from_secs(10),
+    Duration::from_secs(60),
+  );
+  counter.update(|c| *c += 1).unwrap();
+  // Not written yet
+  let event = events.try_recv().unwrap();
+  assert_eq!(event.kind, observer::ChangeKind::Queued);
+  assert_eq!(event.version, version);
+  counter.flush().unwrap();
+  assert!(events.try_recv().is_err());
+  counter.disable_write_behind().unwrap();
+  counter.update(|c| *c += 1).unwrap();
+  let event = events.try_recv().unwrap();
+  assert_eq!(event.kind, observer::ChangeKind::Saved);
+  assert_eq!(event.version, version + 2);
+}